@@ -0,0 +1,316 @@
+use std::sync::Arc;
+
+use reqwest::{Client, Method, RequestBuilder, StatusCode};
+use serde::{Serialize, Deserialize};
+use serde::de::DeserializeOwned;
+
+use log::warn;
+
+use crate::TokenManager;
+use crate::error::{Error, Result};
+use crate::playback::Playback;
+use crate::song::Episode;
+use crate::throttle::{MAX_QUEUE_WAIT, Throttle, ThrottleReason};
+
+pub const DEFAULT_API_URL: &str = "https://api.spotify.com/v1";
+/// Without this Spotify reports a playing episode with no `item`.
+const ADDITIONAL_TYPES: &str = "additional_types=track,episode";
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SpotifyOffset {
+    pub position: u32,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SpotifyPlay {
+    pub uris: Option<Vec<String>>,
+    pub context_uri: Option<String>,
+    pub offset: Option<SpotifyOffset>,
+    pub position_ms: Option<u32>,
+    /// Start an episode where the user left off instead of at `position_ms`. Not sent to Spotify.
+    #[serde(default, skip_serializing)]
+    pub resume: bool,
+}
+
+impl SpotifyPlay {
+    /// The uri playback starts at when it's one of `uris`.
+    pub fn start_uri(&self) -> Option<&str> {
+        let position = self.offset.as_ref().map_or(0, |offset| offset.position) as usize;
+        self.uris.as_ref()?.get(position).map(String::as_str)
+    }
+}
+
+/// One page of a list, as Spotify returns them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Page<T> {
+    pub href: Option<String>,
+    pub items: Vec<T>,
+    pub limit: u32,
+    pub next: Option<String>,
+    pub offset: u32,
+    pub previous: Option<String>,
+    pub total: u32,
+}
+
+/// Which page to ask for, Spotify's defaults apply to anything left out.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub struct PageRequest {
+    pub limit: Option<u32>,
+    pub offset: Option<u32>,
+}
+
+impl PageRequest {
+    /// The query string for this page, including the `?`, or an empty string.
+    fn query(&self) -> String {
+        let params: Vec<String> = [("limit", self.limit), ("offset", self.offset)]
+            .iter()
+            .filter_map(|(name, value)| value.map(|value| format!("{}={}", name, value)))
+            .collect();
+
+        if params.is_empty() {
+            String::new()
+        } else {
+            format!("?{}", params.join("&"))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpotifyUser {
+    pub id: String,
+    pub display_name: Option<String>,
+}
+
+/// The error object Spotify puts in non-success responses.
+#[derive(Debug, Deserialize)]
+struct ErrorBody {
+    error: ErrorObject,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorObject {
+    message: String,
+    reason: Option<String>,
+}
+
+/// Typed client for the Spotify Web API.
+///
+/// Owns a single pooled HTTP client and uses the shared `TokenManager` for
+/// authorization. The base URL can be changed to point at a mock server.
+#[derive(Debug)]
+pub struct SpotifyApi {
+    client: Client,
+    tokens: Arc<TokenManager>,
+    throttle: Throttle,
+    base_url: String,
+}
+
+impl SpotifyApi {
+    pub fn new(tokens: Arc<TokenManager>, base_url: &str) -> Self {
+        let client = Client::builder()
+            .user_agent("BeamNG-Spotify")
+            .build()
+            .unwrap_or_else(|_| Client::new());
+
+        SpotifyApi {
+            client,
+            tokens,
+            throttle: Throttle::default(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    pub fn tokens(&self) -> &TokenManager {
+        &self.tokens
+    }
+
+    /// Base URL of the Web API, `https://api.spotify.com/v1` unless configured otherwise.
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    pub fn throttle(&self) -> &Throttle {
+        &self.throttle
+    }
+
+    /// Whether `error` means Spotify couldn't be reached rather than it refusing the request.
+    pub fn is_unreachable(&self, error: &Error) -> bool {
+        match error {
+            Error::Network(_) | Error::Throttled { .. } => true,
+            Error::Spotify { status, .. } => status.is_server_error() || *status == StatusCode::TOO_MANY_REQUESTS,
+            _ => false,
+        }
+    }
+
+    /// Sends a request to `path` (relative to the base URL) and returns the body, or
+    /// `None` for `204 No Content`.
+    ///
+    /// While throttled, waits for the window to pass if it's about to, and fails with
+    /// `Error::Throttled` otherwise.
+    async fn request(&self, method: Method, path: &str, body: Option<String>) -> Result<Option<String>> {
+        if let Some((reason, remaining)) = self.throttle.window() {
+            if remaining > MAX_QUEUE_WAIT {
+                return Err(Error::Throttled { reason, retry_after: remaining });
+            }
+
+            tokio::time::sleep(remaining).await;
+        }
+
+        let url = format!("{}{}", self.base_url, path);
+        let build = |access_token: &str| -> RequestBuilder {
+            let request = self.client
+                .request(method.clone(), &url)
+                .header("Authorization", format!("Bearer {}", access_token));
+
+            match &body {
+                Some(body) => request
+                    .header("Content-Type", "application/json")
+                    .body(body.clone()),
+                None => request.header("Content-Length", "0"),
+            }
+        };
+
+        let response = self.tokens.send(build).await?;
+
+        let status = response.status();
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            let retry_after = response.headers()
+                .get("Retry-After")
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.trim().parse().ok());
+
+            let retry_after = self.throttle.rate_limited(retry_after);
+            warn!("Rate limited by Spotify, pausing requests for {:.1}s", retry_after.as_secs_f32());
+            return Err(Error::Throttled { reason: ThrottleReason::RateLimited, retry_after });
+        }
+
+        if status.is_server_error() {
+            let backoff = self.throttle.server_error();
+            warn!("Spotify returned {}, backing off for {:.1}s", status, backoff.as_secs_f32());
+        } else {
+            self.throttle.success();
+        }
+
+        let text = response.text().await?;
+
+        if !status.is_success() {
+            let (message, reason) = match serde_json::from_str::<ErrorBody>(&text) {
+                Ok(body) => (body.error.message, body.error.reason),
+                Err(_) => (text, None),
+            };
+
+            return Err(Error::Spotify { status, message, reason });
+        }
+
+        if status == StatusCode::NO_CONTENT || text.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(text))
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path: &str) -> Result<Option<T>> {
+        match self.request(Method::GET, path, None).await? {
+            Some(text) => Ok(Some(serde_json::from_str(&text)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Like `get`, but an empty response is an error.
+    async fn get_required<T: DeserializeOwned>(&self, path: &str) -> Result<T> {
+        match self.request(Method::GET, path, None).await? {
+            Some(text) => Ok(serde_json::from_str(&text)?),
+            None => Err(Error::Spotify {
+                status: StatusCode::NO_CONTENT,
+                message: format!("Empty response from {}", path),
+                reason: None,
+            }),
+        }
+    }
+
+    /// The full playback state including the active device, or `None` if there's no active device.
+    pub async fn player(&self) -> Result<Option<Playback>> {
+        self.get(&format!("/me/player?{}", ADDITIONAL_TYPES)).await
+    }
+
+    pub async fn current_user(&self) -> Result<SpotifyUser> {
+        self.get_required("/me").await
+    }
+
+    pub async fn next(&self) -> Result<()> {
+        self.request(Method::POST, "/me/player/next", None).await?;
+        Ok(())
+    }
+
+    pub async fn previous(&self) -> Result<()> {
+        self.request(Method::POST, "/me/player/previous", None).await?;
+        Ok(())
+    }
+
+    pub async fn play(&self, play: &SpotifyPlay) -> Result<()> {
+        let body = serde_json::to_string(play)?;
+        self.request(Method::PUT, "/me/player/play", Some(body)).await?;
+        Ok(())
+    }
+
+    pub async fn pause(&self) -> Result<()> {
+        self.request(Method::PUT, "/me/player/pause", None).await?;
+        Ok(())
+    }
+
+    pub async fn seek(&self, position_ms: u32) -> Result<()> {
+        self.request(Method::PUT, &format!("/me/player/seek?position_ms={}", position_ms), None).await?;
+        Ok(())
+    }
+
+    pub async fn volume(&self, volume_percent: u32) -> Result<()> {
+        self.request(Method::PUT, &format!("/me/player/volume?volume_percent={}", volume_percent), None).await?;
+        Ok(())
+    }
+
+    pub async fn playlists(&self, page: &PageRequest) -> Result<Page<serde_json::Value>> {
+        self.get_required(&format!("/me/playlists{}", page.query())).await
+    }
+
+    pub async fn playlist_tracks(&self, playlist_id: &str, page: &PageRequest) -> Result<Page<serde_json::Value>> {
+        self.get_required(&format!("/playlists/{}/tracks{}", playlist_id, page.query())).await
+    }
+
+    pub async fn albums(&self, page: &PageRequest) -> Result<Page<serde_json::Value>> {
+        self.get_required(&format!("/me/albums{}", page.query())).await
+    }
+
+    pub async fn top_tracks(&self, page: &PageRequest) -> Result<Page<serde_json::Value>> {
+        self.get_required(&format!("/me/top/tracks{}", page.query())).await
+    }
+
+    /// Shows the user follows.
+    pub async fn shows(&self, page: &PageRequest) -> Result<Page<serde_json::Value>> {
+        self.get_required(&format!("/me/shows{}", page.query())).await
+    }
+
+    pub async fn show_episodes(&self, show_id: &str, page: &PageRequest) -> Result<Page<serde_json::Value>> {
+        self.get_required(&format!("/shows/{}/episodes{}", show_id, page.query())).await
+    }
+
+    pub async fn episode(&self, episode_id: &str) -> Result<Episode> {
+        self.get_required(&format!("/episodes/{}", episode_id)).await
+    }
+
+    /// The page after `page`, or `None` if it was the last one.
+    pub async fn next_page<T: DeserializeOwned>(&self, page: &Page<T>) -> Result<Option<Page<T>>> {
+        let next = match &page.next {
+            Some(next) => next,
+            None => return Ok(None),
+        };
+
+        // Only follow links back to the API, the access token goes along.
+        match next.strip_prefix(&self.base_url) {
+            Some(path) => Ok(Some(self.get_required(path).await?)),
+            None => {
+                warn!("Not following next page link outside the API: {}", next);
+                Ok(None)
+            }
+        }
+    }
+}
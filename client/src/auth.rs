@@ -0,0 +1,335 @@
+use std::io::{BufRead, Write};
+
+use actix_web::{get, post, Responder, HttpResponse, ResponseError, web};
+use actix_web::http::StatusCode;
+use log::{info, error};
+use reqwest::{Client, Url};
+use serde::{Serialize, Deserialize};
+
+use client::{AuthFlow, TokenManager, pkce, scopes};
+use client::api::{SpotifyApi, SpotifyUser};
+use client::config::Config;
+use client::error::Error;
+use client::login::{LoginAttempt, PendingLogins};
+
+#[derive(Debug, Deserialize)]
+pub struct LoginRequest {
+    /// Extra space separated scopes to ask for on top of the default ones.
+    scope: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuthRequest {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuthStatus {
+    pub logged_in: bool,
+    pub profile: String,
+    pub user: Option<SpotifyUser>,
+    pub scopes: Vec<String>,
+    /// Scopes this build uses that the user hasn't granted yet.
+    pub missing_scopes: Vec<String>,
+    /// Seconds until the current access token expires.
+    pub token_expires_in: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuthStart {
+    pub url: String,
+    pub opened: bool,
+}
+
+/// Escapes `text` for use in HTML, messages can contain query parameters and Spotify's responses.
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+fn error_page(status: StatusCode, message: &str) -> HttpResponse {
+    error!("Login failed: {}", message);
+
+    HttpResponse::build(status)
+        .content_type("text/html; charset=utf-8")
+        .body(format!("<html><head><title>BeamNG-Spotify</title></head><body><h2>Login failed</h2><p>{}</p><p><a href=\"/login\">Try again</a></p></body></html>", escape_html(message)))
+}
+
+/// Starts a login for the selected profile and returns the Spotify authorize URL and its `state`.
+///
+/// Asks for every scope this build needs plus any previously granted ones and `extra_scopes`,
+/// so logging in again never loses access the user already gave.
+pub fn authorize_url(config: &Config, tokens: &TokenManager, pending: &PendingLogins, extra_scopes: &[String]) -> (String, String) {
+    let client_id = dotenv::var("SPOTIFY_CLIENT_ID").expect("SPOTIFY_CLIENT_ID not set in .env");
+
+    let code_verifier = match tokens.flow() {
+        AuthFlow::Pkce => Some(pkce::generate_code_verifier()),
+        AuthFlow::ClientSecret => None,
+    };
+
+    let granted = tokens.credentials().get().granted_scopes.unwrap_or_default();
+    let scope = scopes::union(&scopes::union(scopes::ALL, &granted), extra_scopes).join("%20");
+
+    let mut redirect_url = format!("{}/authorize?response_type=code&client_id={}&scope={}&redirect_uri={}", tokens.accounts_url(), client_id, scope, config.redirect_uri());
+
+    if let Some(code_verifier) = &code_verifier {
+        redirect_url.push_str(&format!("&code_challenge_method=S256&code_challenge={}", pkce::code_challenge(code_verifier)));
+    }
+
+    let profile = tokens.credentials().current_profile();
+    info!("Logging in to profile \"{}\"", profile);
+
+    let state = pending.start(&profile, code_verifier);
+    redirect_url.push_str(&format!("&state={}", state));
+
+    (redirect_url, state)
+}
+
+/// Exchanges an authorization code for tokens and stores the refresh token in the
+/// profile the login was started for.
+pub async fn exchange_code(config: &Config, tokens: &TokenManager, attempt: LoginAttempt, code: &str) -> Result<(), (StatusCode, String)> {
+    let client_secret = tokens.credentials().client_secret();
+    let flow = AuthFlow::configured(client_secret.as_deref());
+    let code_verifier = attempt.code_verifier;
+    if flow == AuthFlow::Pkce && code_verifier.is_none() {
+        return Err((StatusCode::BAD_REQUEST, "This login was started without PKCE, please try again.".to_string()));
+    }
+
+    let redirect_uri = config.redirect_uri();
+    let mut params = vec![
+        ("grant_type", "authorization_code"),
+        ("code", code),
+        ("redirect_uri", redirect_uri.as_str()),
+    ];
+
+    if let Some(code_verifier) = &code_verifier {
+        params.push(("code_verifier", code_verifier));
+    }
+
+    let token_request = flow.token_request(&Client::new(), tokens.accounts_url(), client_secret.as_deref(), &params);
+
+    let token_response = match token_request.send().await {
+        Ok(response) => response.text().await.unwrap_or_default(),
+        Err(e) => return Err((StatusCode::BAD_GATEWAY, format!("Failed sending token request: {}", e))),
+    };
+
+    let json: client::SpotifyReturn = match serde_json::from_str(&token_response) {
+        Ok(json) => json,
+        Err(_) => return Err((StatusCode::BAD_GATEWAY, format!("Spotify returned an invalid token response: {}", token_response))),
+    };
+
+    let refresh_token = match json.refresh_token {
+        Some(refresh_token) => refresh_token,
+        None => return Err((StatusCode::BAD_GATEWAY, format!("Spotify didn't return a refresh token: {}", token_response))),
+    };
+
+    let granted_scopes = json.scope.as_deref().map(scopes::parse);
+    if let Err(e) = tokens.credentials().update_profile(&attempt.profile, |c| {
+        c.refresh_token = Some(refresh_token);
+        c.granted_scopes = granted_scopes;
+    }) {
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, format!("Failed storing refresh token: {}", e)));
+    }
+
+    // Make sure the next request picks up the token for the new login.
+    tokens.invalidate().await;
+
+    Ok(())
+}
+
+#[get("/login")]
+async fn login(config: web::Data<Config>, tokens: web::Data<TokenManager>, pending: web::Data<PendingLogins>, query: web::Query<LoginRequest>) -> impl Responder {
+    let extra_scopes = scopes::parse(query.scope.as_deref().unwrap_or_default());
+    let (redirect_url, _) = authorize_url(&config, &tokens, &pending, &extra_scopes);
+    HttpResponse::Found().append_header(("Location", redirect_url)).finish()
+}
+
+#[get("/api/v1/callback")]
+async fn callback(config: web::Data<Config>, tokens: web::Data<TokenManager>, pending: web::Data<PendingLogins>, info: web::Query<AuthRequest>) -> impl Responder {
+    let attempt = match &info.state {
+        Some(state) => pending.take(state),
+        None => return error_page(StatusCode::BAD_REQUEST, "The callback is missing its state parameter."),
+    };
+
+    let attempt = match attempt {
+        Some(attempt) => attempt,
+        None => return error_page(StatusCode::BAD_REQUEST, "This login wasn't started by this client or has expired."),
+    };
+
+    if let Some(error) = &info.error {
+        if error == "access_denied" {
+            return error_page(StatusCode::FORBIDDEN, "Access was denied, the client needs your permission to control Spotify.");
+        }
+
+        return error_page(StatusCode::BAD_REQUEST, &format!("Spotify returned an error: {}", error));
+    }
+
+    let code = match &info.code {
+        Some(code) => code.clone(),
+        None => return error_page(StatusCode::BAD_REQUEST, "The callback is missing its code parameter."),
+    };
+
+    if let Err((status, message)) = exchange_code(&config, &tokens, attempt, &code).await {
+        return error_page(status, &message);
+    }
+
+    info!("Got refresh token, you can now close the browser window and continue...");
+
+    HttpResponse::Ok().body("Got refresh token, you can now close the browser window and continue...")
+}
+
+/// Logs in without a local browser: prints the authorize URL and reads the redirected
+/// URL (or just its `code`) from stdin. Keeps asking until a login succeeds, or gives up
+/// if stdin is closed and leaves the login to the browser flow.
+pub async fn headless_login(config: &Config, tokens: &TokenManager, pending: &PendingLogins) {
+    loop {
+        let (redirect_url, state) = authorize_url(config, tokens, pending, &[]);
+
+        info!("Open this URL in a browser on any device and log in:\n\n    {}\n", redirect_url);
+        info!("The browser will then fail to load {}, that's expected.", config.redirect_uri());
+        print!("Paste the full URL from the address bar (or just the code) and press enter: ");
+        let _ = std::io::stdout().flush();
+
+        let input = tokio::task::spawn_blocking(|| {
+            let mut line = String::new();
+            // Nothing read means stdin was closed, like when running as a service.
+            std::io::stdin().lock().read_line(&mut line).map(|read| (read > 0).then_some(line))
+        }).await;
+
+        let input = match input {
+            Ok(Ok(Some(line))) => line.trim().to_string(),
+            Ok(Ok(None)) => {
+                error!("Stdin was closed before a code was entered, log in through {}/login instead", config.base_url());
+                return;
+            }
+            _ => {
+                error!("Failed reading from stdin");
+                std::process::exit(1);
+            }
+        };
+
+        let (code, returned_state, error) = parse_redirect(&input);
+
+        let attempt = pending.take(&state);
+        if let Some(error) = error {
+            error!("Spotify returned an error: {}", error);
+            continue;
+        }
+
+        if returned_state.is_some_and(|returned_state| returned_state != state) {
+            error!("That URL belongs to a different login, please use the URL printed above");
+            continue;
+        }
+
+        let (code, attempt) = match (code, attempt) {
+            (Some(code), Some(attempt)) => (code, attempt),
+            (None, _) => {
+                error!("Couldn't find a code in the input");
+                continue;
+            }
+            (_, None) => {
+                error!("The login expired, please try again");
+                continue;
+            }
+        };
+
+        match exchange_code(config, tokens, attempt, &code).await {
+            Ok(()) => {
+                info!("Got refresh token, continuing...");
+                return;
+            }
+            Err((_, message)) => error!("Login failed: {}", message),
+        }
+    }
+}
+
+/// Splits pasted input into `(code, state, error)`. Anything that isn't a URL is taken as a bare code.
+fn parse_redirect(input: &str) -> (Option<String>, Option<String>, Option<String>) {
+    let url = match Url::parse(input) {
+        Ok(url) => url,
+        Err(_) if input.is_empty() => return (None, None, None),
+        Err(_) => return (Some(input.to_string()), None, None),
+    };
+
+    let mut code = None;
+    let mut state = None;
+    let mut error = None;
+    for (key, value) in url.query_pairs() {
+        match key.as_ref() {
+            "code" => code = Some(value.into_owned()),
+            "state" => state = Some(value.into_owned()),
+            "error" => error = Some(value.into_owned()),
+            _ => {}
+        }
+    }
+
+    (code, state, error)
+}
+
+#[get("/api/v1/auth/status")]
+async fn auth_status(api: web::Data<SpotifyApi>) -> impl Responder {
+    let tokens = api.tokens();
+    let profile = tokens.credentials().current_profile();
+
+    let token_status = match tokens.status().await {
+        Ok(token_status) => token_status,
+        // Still logged in as far as we know, Spotify just couldn't confirm it.
+        Err(e @ (Error::Network(_) | Error::Spotify { .. })) => return e.error_response(),
+        Err(_) => return HttpResponse::Ok().json(AuthStatus {
+            logged_in: false,
+            profile,
+            user: None,
+            scopes: Vec::new(),
+            missing_scopes: Vec::new(),
+            token_expires_in: None,
+        }),
+    };
+
+    let user = api.current_user().await.ok();
+
+    HttpResponse::Ok().json(AuthStatus {
+        logged_in: true,
+        profile,
+        user,
+        missing_scopes: scopes::missing(&token_status.scopes, scopes::ALL),
+        scopes: token_status.scopes,
+        token_expires_in: Some(token_status.expires_in.as_secs()),
+    })
+}
+
+/// Starts a login from the game by opening the authorize page in the default browser.
+#[post("/api/v1/auth/start")]
+async fn start_login(config: web::Data<Config>, tokens: web::Data<TokenManager>, pending: web::Data<PendingLogins>) -> impl Responder {
+    let (url, _) = authorize_url(&config, &tokens, &pending, &[]);
+
+    let opened = match open::that(&url) {
+        Ok(()) => true,
+        Err(e) => {
+            error!("Failed opening browser: {}", e);
+            false
+        }
+    };
+
+    HttpResponse::Ok().json(AuthStart { url, opened })
+}
+
+#[post("/api/v1/auth/logout")]
+async fn logout(tokens: web::Data<TokenManager>) -> Result<HttpResponse, Error> {
+    tokens.logout().await?;
+
+    info!("Logged out of profile \"{}\"", tokens.credentials().current_profile());
+    Ok(HttpResponse::Ok().finish())
+}
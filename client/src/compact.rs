@@ -0,0 +1,107 @@
+use log::debug;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::api::Page;
+use crate::library::LibraryList;
+use crate::playlist::{Playlist, PlaylistTrack};
+use crate::song::{Episode, Image, Item, SavedAlbum, SavedShow};
+
+/// The parts of a playlist, album or track the in-game UI shows.
+#[derive(Debug, Clone, Serialize)]
+pub struct CompactItem {
+    pub id: String,
+    pub uri: String,
+    pub name: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub artists: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image_url: Option<String>,
+    /// Where to continue an episode from, if it was started and not finished.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resume_position_ms: Option<u64>,
+}
+
+impl From<Item> for CompactItem {
+    fn from(item: Item) -> Self {
+        CompactItem {
+            id: item.id().unwrap_or_default().to_string(),
+            uri: item.uri().to_string(),
+            name: item.name().to_string(),
+            artists: item.artists(),
+            duration_ms: item.duration_ms(),
+            image_url: thumbnail(item.images()),
+            resume_position_ms: match &item {
+                Item::Episode(Episode { resume_point: Some(resume_point), .. }) if !resume_point.fully_played && resume_point.resume_position_ms > 0 => {
+                    Some(resume_point.resume_position_ms)
+                }
+                _ => None,
+            },
+        }
+    }
+}
+
+/// The smallest image, Spotify lists them largest first but not every image has a size.
+fn thumbnail(images: &[Image]) -> Option<String> {
+    images.iter()
+        .min_by_key(|image| image.width.unwrap_or(u32::MAX))
+        .map(|image| image.url.clone())
+}
+
+/// The compact form of `item` from `list`. `None` for items that can't be played, like
+/// removed tracks, or that don't look like what `list` holds.
+pub fn compact(list: &LibraryList, item: &Value) -> Option<CompactItem> {
+    let compacted = match list {
+        LibraryList::Playlists => serde_json::from_value::<Playlist>(item.clone()).map(|playlist| Some(CompactItem {
+            image_url: thumbnail(playlist.images.as_deref().unwrap_or_default()),
+            id: playlist.id,
+            uri: playlist.uri,
+            name: playlist.name,
+            artists: Vec::new(),
+            duration_ms: None,
+            resume_position_ms: None,
+        })),
+        LibraryList::PlaylistTracks(_) => serde_json::from_value::<PlaylistTrack>(item.clone())
+            .map(|track| track.track.map(CompactItem::from)),
+        LibraryList::Albums => serde_json::from_value::<SavedAlbum>(item.clone()).map(|saved| Some(CompactItem {
+            image_url: thumbnail(&saved.album.images),
+            id: saved.album.id.unwrap_or_default(),
+            uri: saved.album.uri.unwrap_or_default(),
+            name: saved.album.name,
+            artists: saved.album.artists.into_iter().map(|artist| artist.name).collect(),
+            duration_ms: None,
+            resume_position_ms: None,
+        })),
+        LibraryList::TopTracks => serde_json::from_value::<Item>(item.clone()).map(|item| Some(item.into())),
+        LibraryList::Shows => serde_json::from_value::<SavedShow>(item.clone()).map(|saved| Some(CompactItem {
+            image_url: thumbnail(&saved.show.images),
+            id: saved.show.id.unwrap_or_default(),
+            uri: saved.show.uri.unwrap_or_default(),
+            name: saved.show.name,
+            artists: saved.show.publisher.into_iter().collect(),
+            duration_ms: None,
+            resume_position_ms: None,
+        })),
+        LibraryList::ShowEpisodes(_) => serde_json::from_value::<Episode>(item.clone()).map(|episode| Some(Item::Episode(episode).into())),
+    };
+
+    compacted.unwrap_or_else(|e| {
+        debug!("Leaving out item that doesn't fit {:?}: {}", list, e);
+        None
+    })
+}
+
+/// `page` with its items made compact.
+pub fn compact_page(list: &LibraryList, page: Page<Value>) -> Page<CompactItem> {
+    Page {
+        items: page.items.iter().filter_map(|item| compact(list, item)).collect(),
+        href: page.href,
+        limit: page.limit,
+        next: page.next,
+        offset: page.offset,
+        previous: page.previous,
+        total: page.total,
+    }
+}
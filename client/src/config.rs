@@ -0,0 +1,284 @@
+use log::{info, warn};
+use serde::{Serialize, Deserialize};
+use std::fs::read_to_string;
+use std::path::Path;
+use std::time::Duration;
+
+const CONFIG_FILE: &str = "config.json";
+
+/// Where the client listens and how Spotify reaches it after a login.
+///
+/// Values are layered, each overriding the previous one: built-in defaults,
+/// `config.json`, environment (or `.env`), then command line flags.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Address the HTTP server binds to.
+    pub host: String,
+    pub port: u16,
+    /// Must match the redirect URI set in the Spotify dashboard. Defaults to the
+    /// callback on `host` and `port`.
+    pub redirect_uri: Option<String>,
+    /// Log in through the console instead of opening a browser.
+    pub headless: bool,
+    /// Also write the player state to `song.json` and `active_device.json` for
+    /// tools that read those files.
+    pub export_files: bool,
+    pub poll: PollConfig,
+    pub library: LibraryConfig,
+    /// Base URL of the Web API, only changed to point at a mock server.
+    pub api_url: String,
+    /// Base URL of the accounts service, only changed to point at a mock server.
+    pub accounts_url: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            host: "localhost".to_string(),
+            port: 8888,
+            redirect_uri: None,
+            headless: false,
+            export_files: false,
+            poll: PollConfig::default(),
+            library: LibraryConfig::default(),
+            api_url: crate::api::DEFAULT_API_URL.to_string(),
+            accounts_url: crate::DEFAULT_ACCOUNTS_URL.to_string(),
+        }
+    }
+}
+
+/// Bounds for the poller's interval in milliseconds, see `poll::next_interval`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PollConfig {
+    /// Right after a command and near the end of a track.
+    pub fast_ms: u64,
+    pub playing_ms: u64,
+    pub paused_ms: u64,
+    /// No active device, or no game client has made a request in a while.
+    pub idle_ms: u64,
+}
+
+impl Default for PollConfig {
+    fn default() -> Self {
+        PollConfig {
+            fast_ms: 250,
+            playing_ms: 1000,
+            paused_ms: 3000,
+            idle_ms: 10000,
+        }
+    }
+}
+
+impl PollConfig {
+    pub fn fast(&self) -> Duration {
+        Duration::from_millis(self.fast_ms)
+    }
+
+    pub fn playing(&self) -> Duration {
+        Duration::from_millis(self.playing_ms)
+    }
+
+    pub fn paused(&self) -> Duration {
+        Duration::from_millis(self.paused_ms)
+    }
+
+    pub fn idle(&self) -> Duration {
+        Duration::from_millis(self.idle_ms)
+    }
+
+    fn apply_env(&mut self) {
+        for (name, value) in [
+            ("SPOTIFY_POLL_FAST_MS", &mut self.fast_ms),
+            ("SPOTIFY_POLL_PLAYING_MS", &mut self.playing_ms),
+            ("SPOTIFY_POLL_PAUSED_MS", &mut self.paused_ms),
+            ("SPOTIFY_POLL_IDLE_MS", &mut self.idle_ms),
+        ] {
+            if let Ok(ms) = dotenv::var(name) {
+                match ms.parse() {
+                    Ok(ms) => *value = ms,
+                    Err(_) => warn!("Ignoring invalid {} \"{}\"", name, ms),
+                }
+            }
+        }
+    }
+}
+
+/// Background fetching of the playlists, their tracks, saved albums and top tracks.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LibraryConfig {
+    /// Keep the library cached so its endpoints answer right away.
+    pub prefetch: bool,
+    /// Seconds before cached lists are fetched again.
+    pub ttl_secs: u64,
+    /// Keep a copy of the playlists and their tracks in `library.db`, served when Spotify
+    /// can't be reached. Needs `prefetch`.
+    pub mirror: bool,
+}
+
+impl Default for LibraryConfig {
+    fn default() -> Self {
+        LibraryConfig {
+            prefetch: true,
+            ttl_secs: 300,
+            mirror: true,
+        }
+    }
+}
+
+impl LibraryConfig {
+    pub fn ttl(&self) -> Duration {
+        Duration::from_secs(self.ttl_secs)
+    }
+
+    fn apply_env(&mut self) {
+        if let Ok(prefetch) = dotenv::var("SPOTIFY_LIBRARY_PREFETCH") {
+            self.prefetch = prefetch == "1" || prefetch.eq_ignore_ascii_case("true");
+        }
+
+        if let Ok(mirror) = dotenv::var("SPOTIFY_LIBRARY_MIRROR") {
+            self.mirror = mirror == "1" || mirror.eq_ignore_ascii_case("true");
+        }
+
+        if let Ok(secs) = dotenv::var("SPOTIFY_LIBRARY_TTL_SECS") {
+            match secs.parse() {
+                Ok(secs) => self.ttl_secs = secs,
+                Err(_) => warn!("Ignoring invalid SPOTIFY_LIBRARY_TTL_SECS \"{}\"", secs),
+            }
+        }
+    }
+}
+
+impl Config {
+    /// Loads the effective configuration from all sources.
+    pub fn load() -> Self {
+        let args: Vec<String> = std::env::args().skip(1).collect();
+
+        let path = flag_value(&args, "--config").unwrap_or_else(|| CONFIG_FILE.to_string());
+        let mut config = Config::from_file(&path);
+        config.apply_env();
+        config.apply_args(&args);
+
+        // Only prefetching fetches whole lists, so nothing would keep the mirror up to date.
+        if config.library.mirror && !config.library.prefetch {
+            warn!("The library mirror needs prefetch, disabling it");
+            config.library.mirror = false;
+        }
+
+        config
+    }
+
+    fn from_file(path: &str) -> Self {
+        if !Path::new(path).exists() {
+            return Config::default();
+        }
+
+        let contents = match read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) => {
+                warn!("Failed reading {}: {}, using defaults", path, e);
+                return Config::default();
+            }
+        };
+
+        match serde_json::from_str(&contents) {
+            Ok(config) => {
+                info!("Loaded configuration from {}", path);
+                config
+            }
+            Err(e) => {
+                warn!("Invalid configuration in {}: {}, using defaults", path, e);
+                Config::default()
+            }
+        }
+    }
+
+    fn apply_env(&mut self) {
+        if let Ok(host) = dotenv::var("SPOTIFY_HOST") {
+            self.host = host;
+        }
+
+        if let Ok(port) = dotenv::var("SPOTIFY_PORT") {
+            match port.parse() {
+                Ok(port) => self.port = port,
+                Err(_) => warn!("Ignoring invalid SPOTIFY_PORT \"{}\"", port),
+            }
+        }
+
+        if let Ok(redirect_uri) = dotenv::var("SPOTIFY_REDIRECT_URI") {
+            self.redirect_uri = Some(redirect_uri);
+        }
+
+        if let Ok(api_url) = dotenv::var("SPOTIFY_API_URL") {
+            self.api_url = api_url;
+        }
+
+        if let Ok(accounts_url) = dotenv::var("SPOTIFY_ACCOUNTS_URL") {
+            self.accounts_url = accounts_url;
+        }
+
+        if let Ok(export_files) = dotenv::var("SPOTIFY_EXPORT_FILES") {
+            self.export_files = export_files == "1" || export_files.eq_ignore_ascii_case("true");
+        }
+
+        self.poll.apply_env();
+        self.library.apply_env();
+
+        if let Ok(mode) = dotenv::var("SPOTIFY_LOGIN_MODE") {
+            self.headless = mode.eq_ignore_ascii_case("headless");
+        }
+    }
+
+    fn apply_args(&mut self, args: &[String]) {
+        if let Some(host) = flag_value(args, "--host") {
+            self.host = host;
+        }
+
+        if let Some(port) = flag_value(args, "--port") {
+            match port.parse() {
+                Ok(port) => self.port = port,
+                Err(_) => warn!("Ignoring invalid --port \"{}\"", port),
+            }
+        }
+
+        if let Some(redirect_uri) = flag_value(args, "--redirect-uri") {
+            self.redirect_uri = Some(redirect_uri);
+        }
+
+        if args.iter().any(|arg| arg == "--headless") {
+            self.headless = true;
+        }
+
+        if args.iter().any(|arg| arg == "--export-files") {
+            self.export_files = true;
+        }
+    }
+
+    /// `host:port` for binding the server.
+    pub fn bind_address(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+
+    /// Base URL of the local server, e.g. `http://localhost:8888`.
+    pub fn base_url(&self) -> String {
+        format!("http://{}:{}", self.host, self.port)
+    }
+
+    pub fn redirect_uri(&self) -> String {
+        self.redirect_uri.clone().unwrap_or_else(|| format!("{}/api/v1/callback", self.base_url()))
+    }
+}
+
+/// Value of `--name value` or `--name=value`.
+fn flag_value(args: &[String], name: &str) -> Option<String> {
+    let prefix = format!("{}=", name);
+    args.iter().enumerate().find_map(|(i, arg)| {
+        if arg == name {
+            args.get(i + 1).cloned()
+        } else {
+            arg.strip_prefix(&prefix).map(String::from)
+        }
+    })
+}
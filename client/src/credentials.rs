@@ -0,0 +1,416 @@
+use argon2::Argon2;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use log::{info, warn};
+use rand::Rng;
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+const CREDENTIALS_FILE: &str = "credentials.enc";
+const LEGACY_REFRESH_TOKEN_FILE: &str = "refresh_token.txt";
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// The secrets a profile needs to talk to Spotify, stored encrypted on disk.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Credentials {
+    pub refresh_token: Option<String>,
+    /// Scopes the user granted at their last login or token refresh.
+    pub granted_scopes: Option<Vec<String>>,
+}
+
+/// Everything kept in the credential file: the app's client secret, shared by every
+/// profile, and one set of credentials per profile.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Vault {
+    #[serde(default)]
+    client_secret: Option<String>,
+    current_profile: String,
+    profiles: BTreeMap<String, Credentials>,
+}
+
+impl Default for Vault {
+    fn default() -> Self {
+        Vault {
+            client_secret: None,
+            current_profile: DEFAULT_PROFILE.to_string(),
+            profiles: BTreeMap::from([(DEFAULT_PROFILE.to_string(), Credentials::default())]),
+        }
+    }
+}
+
+/// How the encryption key of the credential file is derived.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum KeySource {
+    /// Argon2id over `SPOTIFY_CREDENTIALS_PASSPHRASE`.
+    Passphrase,
+    /// Random key kept in the user's local data directory.
+    Machine,
+}
+
+/// A derived file key and the salt it was derived from.
+#[derive(Clone, Copy)]
+struct FileKey {
+    source: KeySource,
+    salt: [u8; 16],
+    key: [u8; 32],
+}
+
+impl std::fmt::Debug for FileKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FileKey").field("source", &self.source).finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct EncryptedFile {
+    key_source: KeySource,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+/// Encrypted on-disk store for the client secret and the refresh token of every profile.
+///
+/// `get` and `update` act on the currently selected profile, so callers follow a
+/// profile switch without having to know about profiles at all.
+///
+/// The file is encrypted with XChaCha20-Poly1305. The key is derived from
+/// `SPOTIFY_CREDENTIALS_PASSPHRASE` when it's set, otherwise from a per-machine key
+/// that lives outside the working directory. Writes are atomic and, on unix, only
+/// readable by the current user.
+#[derive(Debug)]
+pub struct CredentialStore {
+    path: PathBuf,
+    vault: Mutex<Vault>,
+    /// Key writes are encrypted with. Argon2 is slow, so it's derived once and reused
+    /// with a fresh nonce for every write.
+    key: Mutex<Option<FileKey>>,
+}
+
+impl CredentialStore {
+    /// Opens `credentials.enc` in the working directory.
+    pub fn open() -> io::Result<Self> {
+        Self::open_at(CREDENTIALS_FILE)
+    }
+
+    /// Opens the store at `path`, importing `refresh_token.txt` and a client secret from
+    /// `.env` if they're still around in the same directory.
+    pub fn open_at<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let (vault, key) = if path.exists() {
+            let (vault, key) = decrypt(&fs::read_to_string(&path)?)?;
+            // Keep writing with the file's key unless a passphrase was set or removed since.
+            (vault, Some(key).filter(|key| key.source == KeySource::configured()))
+        } else {
+            (Vault::default(), None)
+        };
+
+        let store = CredentialStore {
+            path,
+            vault: Mutex::new(vault),
+            key: Mutex::new(key),
+        };
+
+        store.migrate()?;
+        Ok(store)
+    }
+
+    /// Returns a copy of the current profile's credentials.
+    pub fn get(&self) -> Credentials {
+        let vault = self.vault.lock().unwrap();
+        vault.profiles.get(&vault.current_profile).cloned().unwrap_or_default()
+    }
+
+    /// Returns a copy of the credentials of `profile`, if it exists.
+    pub fn get_profile(&self, profile: &str) -> Option<Credentials> {
+        self.vault.lock().unwrap().profiles.get(profile).cloned()
+    }
+
+    /// Applies `f` to the current profile's credentials and writes them back to disk.
+    pub fn update<F: FnOnce(&mut Credentials)>(&self, f: F) -> io::Result<()> {
+        let profile = self.current_profile();
+        self.update_profile(&profile, f)
+    }
+
+    /// Applies `f` to the credentials of `profile`, creating it if needed, and writes them back to disk.
+    pub fn update_profile<F: FnOnce(&mut Credentials)>(&self, profile: &str, f: F) -> io::Result<()> {
+        let mut vault = self.vault.lock().unwrap();
+        f(vault.profiles.entry(profile.to_string()).or_default());
+        self.write(&vault)
+    }
+
+    /// The client secret of the Spotify app, used by every profile.
+    pub fn client_secret(&self) -> Option<String> {
+        self.vault.lock().unwrap().client_secret.clone().filter(|secret| !secret.is_empty())
+    }
+
+    /// Replaces the client secret and writes it to disk.
+    pub fn set_client_secret(&self, client_secret: Option<String>) -> io::Result<()> {
+        let mut vault = self.vault.lock().unwrap();
+        vault.client_secret = client_secret;
+        self.write(&vault)
+    }
+
+    pub fn current_profile(&self) -> String {
+        self.vault.lock().unwrap().current_profile.clone()
+    }
+
+    /// Names of all profiles, sorted.
+    pub fn profiles(&self) -> Vec<String> {
+        self.vault.lock().unwrap().profiles.keys().cloned().collect()
+    }
+
+    /// Selects `profile`, creating an empty one if it doesn't exist yet.
+    pub fn switch_profile(&self, profile: &str) -> io::Result<()> {
+        let mut vault = self.vault.lock().unwrap();
+        vault.profiles.entry(profile.to_string()).or_default();
+        vault.current_profile = profile.to_string();
+        self.write(&vault)
+    }
+
+    /// Deletes `profile` and its credentials. The selected profile can't be removed.
+    /// Returns `false` if there was no such profile.
+    pub fn remove_profile(&self, profile: &str) -> io::Result<bool> {
+        let mut vault = self.vault.lock().unwrap();
+        if vault.current_profile == profile {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "can't remove the selected profile"));
+        }
+
+        if vault.profiles.remove(profile).is_none() {
+            return Ok(false);
+        }
+
+        self.write(&vault)?;
+        Ok(true)
+    }
+
+    fn write(&self, vault: &Vault) -> io::Result<()> {
+        write_atomic(&self.path, encrypt(vault, &self.file_key()?)?)
+    }
+
+    /// The key to write with, derived with a new salt on first use.
+    fn file_key(&self) -> io::Result<FileKey> {
+        let mut key = self.key.lock().unwrap();
+        if let Some(key) = *key {
+            return Ok(key);
+        }
+
+        let salt: [u8; 16] = rand::thread_rng().gen();
+        let (source, derived) = derive_key(None, &salt)?;
+        let derived = FileKey { source, salt, key: derived };
+        *key = Some(derived);
+        Ok(derived)
+    }
+
+    /// Imports the files older versions kept next to the store.
+    fn migrate(&self) -> io::Result<()> {
+        let dir = self.path.parent().unwrap_or(Path::new(""));
+
+        let legacy_refresh_token = dir.join(LEGACY_REFRESH_TOKEN_FILE);
+        if legacy_refresh_token.exists() {
+            let refresh_token = fs::read_to_string(&legacy_refresh_token)?.trim().to_string();
+            if !refresh_token.is_empty() {
+                self.update(|c| c.refresh_token = Some(refresh_token))?;
+            }
+
+            fs::remove_file(&legacy_refresh_token)?;
+            info!("Imported {} into {}", legacy_refresh_token.display(), self.path.display());
+        }
+
+        let env_file = dir.join(".env");
+        let client_secret = env_file_secret(&env_file)?.unwrap_or_default();
+        if !client_secret.is_empty() {
+            self.set_client_secret(Some(client_secret))?;
+            remove_secret_from_env_file(&env_file)?;
+            info!("Moved SPOTIFY_CLIENT_SECRET from {} into {}", env_file.display(), self.path.display());
+        }
+
+        Ok(())
+    }
+}
+
+fn encrypt(vault: &Vault, key: &FileKey) -> io::Result<String> {
+    let nonce: [u8; 24] = rand::thread_rng().gen();
+
+    let plaintext = serde_json::to_vec(vault)?;
+    let ciphertext = XChaCha20Poly1305::new(&key.key.into())
+        .encrypt(XNonce::from_slice(&nonce), plaintext.as_slice())
+        .map_err(|_| io::Error::other("failed encrypting credentials"))?;
+
+    let file = EncryptedFile {
+        key_source: key.source,
+        salt: base64::encode(key.salt),
+        nonce: base64::encode(nonce),
+        ciphertext: base64::encode(ciphertext),
+    };
+
+    Ok(serde_json::to_string_pretty(&file)?)
+}
+
+fn decrypt(contents: &str) -> io::Result<(Vault, FileKey)> {
+    let file: EncryptedFile = serde_json::from_str(contents)?;
+    let invalid = |what: &str| io::Error::new(io::ErrorKind::InvalidData, format!("invalid credential file: {}", what));
+
+    let salt = base64::decode(&file.salt).map_err(|_| invalid("salt"))?;
+    let nonce = base64::decode(&file.nonce).map_err(|_| invalid("nonce"))?;
+    let ciphertext = base64::decode(&file.ciphertext).map_err(|_| invalid("ciphertext"))?;
+    if nonce.len() != 24 {
+        return Err(invalid("nonce"));
+    }
+
+    let salt: [u8; 16] = salt.try_into().map_err(|_| invalid("salt"))?;
+    let (source, key) = derive_key(Some(file.key_source), &salt)?;
+    let file_key = FileKey { source, salt, key };
+    let plaintext = XChaCha20Poly1305::new(&key.into())
+        .decrypt(XNonce::from_slice(&nonce), ciphertext.as_slice())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "failed decrypting credentials, wrong passphrase or machine key?"))?;
+
+    let json: serde_json::Value = serde_json::from_slice(&plaintext)?;
+    let mut vault = if json.get("profiles").is_some() {
+        serde_json::from_value::<Vault>(json.clone())?
+    } else {
+        // Files written before profiles existed hold a single set of credentials.
+        Vault {
+            client_secret: None,
+            current_profile: DEFAULT_PROFILE.to_string(),
+            profiles: BTreeMap::from([(DEFAULT_PROFILE.to_string(), serde_json::from_value(json.clone())?)]),
+        }
+    };
+
+    if vault.client_secret.is_none() {
+        vault.client_secret = legacy_client_secret(&json);
+    }
+
+    Ok((vault, file_key))
+}
+
+/// The client secret of files that stored it with the credentials of a profile.
+fn legacy_client_secret(json: &serde_json::Value) -> Option<String> {
+    let profiles = json.get("profiles").and_then(|profiles| profiles.as_object());
+    let credentials: Vec<&serde_json::Value> = match profiles {
+        Some(profiles) => profiles.values().collect(),
+        None => vec![json],
+    };
+
+    credentials.into_iter()
+        .filter_map(|credentials| credentials.get("client_secret")?.as_str())
+        .find(|secret| !secret.is_empty())
+        .map(str::to_string)
+}
+
+impl KeySource {
+    /// The passphrase if one is set, the machine key otherwise.
+    fn configured() -> Self {
+        if dotenv::var("SPOTIFY_CREDENTIALS_PASSPHRASE").unwrap_or_default().is_empty() {
+            KeySource::Machine
+        } else {
+            KeySource::Passphrase
+        }
+    }
+}
+
+/// Derives the file key. When `key_source` is `None` (writing), the configured source is used.
+fn derive_key(key_source: Option<KeySource>, salt: &[u8]) -> io::Result<(KeySource, [u8; 32])> {
+    let passphrase = dotenv::var("SPOTIFY_CREDENTIALS_PASSPHRASE").unwrap_or_default();
+    let key_source = key_source.unwrap_or_else(KeySource::configured);
+
+    let secret = match key_source {
+        KeySource::Passphrase => {
+            if passphrase.is_empty() {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "credentials are passphrase protected, set SPOTIFY_CREDENTIALS_PASSPHRASE"));
+            }
+
+            passphrase.into_bytes()
+        }
+        KeySource::Machine => machine_key()?.to_vec(),
+    };
+
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(&secret, salt, &mut key)
+        .map_err(|e| io::Error::other(format!("failed deriving key: {}", e)))?;
+
+    Ok((key_source, key))
+}
+
+/// Loads the per-machine key, creating it on first use. Fails if there's no local data
+/// directory, a key next to the credential file wouldn't protect anything.
+fn machine_key() -> io::Result<[u8; 32]> {
+    let path = dirs::data_local_dir()
+        .map(|dir| dir.join("beamng-spotify").join("machine.key"))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no local data directory to keep the machine key in, set SPOTIFY_CREDENTIALS_PASSPHRASE"))?;
+
+    if let Ok(contents) = fs::read(&path) {
+        if let Ok(key) = <[u8; 32]>::try_from(contents.as_slice()) {
+            return Ok(key);
+        }
+
+        warn!("Ignoring invalid machine key at {}", path.display());
+    }
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let key: [u8; 32] = rand::thread_rng().gen();
+    write_atomic(&path, key)?;
+    Ok(key)
+}
+
+/// Writes `contents` to a temporary file next to `path` and renames it into place,
+/// so a crash never leaves a half-written file behind.
+fn write_atomic<P: AsRef<Path>, C: AsRef<[u8]>>(path: P, contents: C) -> io::Result<()> {
+    let path = path.as_ref();
+    let tmp_path = path.with_extension("tmp");
+
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file: File = options.open(&tmp_path)?;
+    file.write_all(contents.as_ref())?;
+    file.sync_all()?;
+    drop(file);
+
+    fs::rename(&tmp_path, path)
+}
+
+/// `SPOTIFY_CLIENT_SECRET` from the `.env` file at `path`, if there is one.
+fn env_file_secret(path: &Path) -> io::Result<Option<String>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let contents = fs::read_to_string(path)?;
+    let secret = contents
+        .lines()
+        .find_map(|line| line.trim_start().strip_prefix("SPOTIFY_CLIENT_SECRET="))
+        .map(|value| value.trim().trim_matches(|c| c == '"' || c == '\'').to_string());
+
+    Ok(secret)
+}
+
+/// Blanks `SPOTIFY_CLIENT_SECRET` in the `.env` file at `path` once it has been moved into the store.
+fn remove_secret_from_env_file(path: &Path) -> io::Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+
+    let contents = fs::read_to_string(path)?;
+    let cleaned: Vec<&str> = contents
+        .lines()
+        .map(|line| if line.trim_start().starts_with("SPOTIFY_CLIENT_SECRET=") { "SPOTIFY_CLIENT_SECRET=" } else { line })
+        .collect();
+
+    write_atomic(path, cleaned.join("\n"))
+}
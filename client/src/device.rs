@@ -1,25 +1,22 @@
 use serde::{Serialize, Deserialize};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Device {
-    pub id: String,
+    /// Spotify may not give an id, for example for some restricted devices.
+    pub id: Option<String>,
     pub is_active: bool,
     pub is_private_session: bool,
+    pub is_restricted: bool,
     pub name: String,
     #[serde(rename = "type")]
     pub type_: String,
-    pub volume_percent: i32,
+    /// `None` for devices whose volume can't be controlled.
+    pub volume_percent: Option<i32>,
+    pub supports_volume: Option<bool>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct SpotifyDevice {
     pub device: Option<Device>,
 }
-
-impl Default for SpotifyDevice {
-    fn default() -> Self {
-        SpotifyDevice {
-            device: None,
-        }
-    }
-}
\ No newline at end of file
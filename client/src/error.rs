@@ -0,0 +1,194 @@
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+use actix_web::{HttpResponse, ResponseError};
+use actix_web::http::StatusCode;
+use log::error;
+use serde::Serialize;
+
+use crate::throttle::ThrottleReason;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Reasons Spotify puts in player errors, see
+/// https://developer.spotify.com/documentation/web-api/reference/#/operations/get-information-about-the-users-current-playback
+const NO_ACTIVE_DEVICE: &str = "NO_ACTIVE_DEVICE";
+const PREMIUM_REQUIRED: &str = "PREMIUM_REQUIRED";
+
+#[derive(Debug)]
+pub enum Error {
+    /// No access token, the selected profile has to log in first.
+    NotLoggedIn,
+    /// The selected profile hasn't granted scopes the endpoint needs.
+    ScopeMissing(Vec<String>),
+    Network(reqwest::Error),
+    /// Spotify is rate limiting us or we're backing off after server errors.
+    Throttled {
+        reason: ThrottleReason,
+        retry_after: Duration,
+    },
+    /// Spotify answered with a non-success status.
+    Spotify {
+        status: reqwest::StatusCode,
+        message: String,
+        reason: Option<String>,
+    },
+    Parse(serde_json::Error),
+    Io(io::Error),
+    BadRequest(String),
+    Forbidden(String),
+    NotFound(String),
+    Conflict(String),
+}
+
+/// Body of every error response.
+#[derive(Debug, Serialize)]
+pub struct ErrorBody {
+    /// Stable machine readable code, e.g. `not_logged_in` or `no_active_device`.
+    pub error: &'static str,
+    pub message: String,
+    /// The reason Spotify gave, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// Milliseconds until requests are sent to Spotify again.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_after_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub missing_scopes: Option<Vec<String>>,
+    /// Path on this server that logs in again asking for the missing scopes as well.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub login_url: Option<String>,
+}
+
+impl Error {
+    fn spotify_reason(&self) -> Option<&str> {
+        match self {
+            Error::Spotify { reason, .. } => reason.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// The code sent as `error` in the response body.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::NotLoggedIn => "not_logged_in",
+            Error::ScopeMissing(_) => "scope_missing",
+            Error::Network(_) => "network_error",
+            Error::Throttled { reason: ThrottleReason::RateLimited, .. } => "rate_limited",
+            Error::Throttled { reason: ThrottleReason::ServerError, .. } => "spotify_unavailable",
+            Error::Spotify { status, message, .. } => match self.spotify_reason() {
+                Some(NO_ACTIVE_DEVICE) => "no_active_device",
+                Some(PREMIUM_REQUIRED) => "premium_required",
+                // Older responses only say so in the message.
+                _ if *status == reqwest::StatusCode::NOT_FOUND && message.contains("No active device") => "no_active_device",
+                _ if *status == reqwest::StatusCode::UNAUTHORIZED => "not_logged_in",
+                _ if *status == reqwest::StatusCode::TOO_MANY_REQUESTS => "rate_limited",
+                _ => "spotify_error",
+            },
+            Error::Parse(_) => "invalid_response",
+            Error::Io(_) => "io_error",
+            Error::BadRequest(_) => "bad_request",
+            Error::Forbidden(_) => "forbidden",
+            Error::NotFound(_) => "not_found",
+            Error::Conflict(_) => "conflict",
+        }
+    }
+
+    /// The JSON body sent for this error.
+    pub fn body(&self) -> ErrorBody {
+        let (missing_scopes, login_url) = match self {
+            Error::ScopeMissing(scopes) => (Some(scopes.clone()), Some(format!("/login?scope={}", scopes.join("%20")))),
+            _ => (None, None),
+        };
+
+        let retry_after = match self {
+            Error::Throttled { retry_after, .. } => Some(*retry_after),
+            _ => None,
+        };
+
+        ErrorBody {
+            error: self.code(),
+            message: self.to_string(),
+            reason: self.spotify_reason().map(String::from),
+            retry_after_ms: retry_after.map(|retry_after| retry_after.as_millis() as u64),
+            missing_scopes,
+            login_url,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NotLoggedIn => write!(f, "Not logged in"),
+            Error::ScopeMissing(scopes) => write!(f, "Missing scopes: {}", scopes.join(" ")),
+            Error::Network(e) => write!(f, "Network error: {}", e),
+            Error::Throttled { reason: ThrottleReason::RateLimited, retry_after } => write!(f, "Too many requests, try again in {:.1}s", retry_after.as_secs_f32()),
+            Error::Throttled { reason: ThrottleReason::ServerError, retry_after } => write!(f, "Spotify is having problems, try again in {:.1}s", retry_after.as_secs_f32()),
+            Error::Spotify { status, message, .. } => write!(f, "Spotify returned {}: {}", status, message),
+            Error::Parse(e) => write!(f, "Invalid response from Spotify: {}", e),
+            Error::Io(e) => write!(f, "IO error: {}", e),
+            Error::BadRequest(message) | Error::Forbidden(message) | Error::NotFound(message) | Error::Conflict(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Error::Network(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Parse(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl ResponseError for Error {
+    fn status_code(&self) -> StatusCode {
+        match self.code() {
+            "not_logged_in" => StatusCode::UNAUTHORIZED,
+            "scope_missing" | "premium_required" | "forbidden" => StatusCode::FORBIDDEN,
+            "no_active_device" | "not_found" => StatusCode::NOT_FOUND,
+            "rate_limited" => StatusCode::TOO_MANY_REQUESTS,
+            "spotify_unavailable" => StatusCode::SERVICE_UNAVAILABLE,
+            "bad_request" => StatusCode::BAD_REQUEST,
+            "conflict" => StatusCode::CONFLICT,
+            "io_error" => StatusCode::INTERNAL_SERVER_ERROR,
+            "network_error" | "invalid_response" => StatusCode::BAD_GATEWAY,
+            // Pass other client errors through, they're usually caused by the request.
+            _ => match self {
+                Error::Spotify { status, .. } if status.is_client_error() => {
+                    StatusCode::from_u16(status.as_u16()).unwrap_or(StatusCode::BAD_GATEWAY)
+                }
+                _ => StatusCode::BAD_GATEWAY,
+            },
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+        if status.is_server_error() {
+            error!("{}", self);
+        }
+
+        let mut response = HttpResponse::build(status);
+        if let Error::Throttled { retry_after, .. } = self {
+            // Retry-After only takes whole seconds.
+            let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+            response.insert_header(("Retry-After", seconds.to_string()));
+        }
+
+        response.json(self.body())
+    }
+}
@@ -0,0 +1,200 @@
+use log::{info, warn};
+use serde::Serialize;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::device::Device;
+use crate::song::{Context, Item};
+use crate::state::{PlayerState, SEEK_TOLERANCE_MS};
+
+/// Events a slow subscriber may fall behind by before it starts missing some.
+const CAPACITY: usize = 256;
+/// Jumping back to within this much of the start of the same track counts as playing it again.
+const REPLAY_WINDOW_MS: u64 = 5000;
+
+/// Something that changed, published by the poller.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    /// A different track started, or the same one started over.
+    TrackChanged {
+        item: Box<Item>,
+        replay: bool,
+    },
+    PlaybackChanged {
+        is_playing: bool,
+    },
+    /// The position jumped somewhere playback alone wouldn't have taken it.
+    Seeked {
+        progress_ms: u64,
+    },
+    /// Sent on every poll while playing.
+    Progress {
+        progress_ms: u64,
+        duration_ms: Option<u32>,
+        /// Unix time in milliseconds `progress_ms` was measured at.
+        server_time: u64,
+    },
+    VolumeChanged {
+        volume_percent: Option<i32>,
+    },
+    /// The active device changed or went away.
+    DeviceChanged {
+        device: Option<Device>,
+    },
+    ShuffleChanged {
+        shuffle_state: bool,
+    },
+    RepeatChanged {
+        repeat_state: String,
+    },
+    /// The playlist, album or artist playing from changed.
+    ContextChanged {
+        context: Option<Context>,
+    },
+    AuthChanged {
+        logged_in: bool,
+        profile: String,
+    },
+}
+
+/// Fans events out to every subscriber: the log, the history and the push endpoints.
+#[derive(Debug)]
+pub struct EventBus {
+    tx: broadcast::Sender<Event>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        EventBus {
+            tx: broadcast::channel(CAPACITY).0,
+        }
+    }
+}
+
+impl EventBus {
+    pub fn publish(&self, event: Event) {
+        // Nobody listening isn't an error.
+        let _ = self.tx.send(event);
+    }
+
+    /// Publishes the events describing the change from `old` to `new`.
+    pub fn publish_changes(&self, old: &PlayerState, new: &PlayerState) {
+        for event in diff(old, new) {
+            self.publish(event);
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.tx.subscribe()
+    }
+}
+
+/// Receives the next event, skipping over any a slow subscriber missed. `None` once the bus is gone.
+pub async fn next_event(rx: &mut broadcast::Receiver<Event>) -> Option<Event> {
+    loop {
+        match rx.recv().await {
+            Ok(event) => return Some(event),
+            Err(RecvError::Lagged(missed)) => warn!("Event subscriber fell behind, skipped {} events", missed),
+            Err(RecvError::Closed) => return None,
+        }
+    }
+}
+
+/// Events describing the change from `old` to `new`.
+pub fn diff(old: &PlayerState, new: &PlayerState) -> Vec<Event> {
+    let mut events = Vec::new();
+
+    let old_device = old.device().device;
+    let new_device = new.device().device;
+    if old_device.as_ref().map(|device| &device.id) != new_device.as_ref().map(|device| &device.id) {
+        events.push(Event::DeviceChanged { device: new_device.clone() });
+    } else if let (Some(old_device), Some(new_device)) = (&old_device, &new_device) {
+        if old_device.volume_percent != new_device.volume_percent {
+            events.push(Event::VolumeChanged { volume_percent: new_device.volume_percent });
+        }
+    }
+
+    let old_playback = old.playback.as_ref();
+    let new_playback = new.playback.as_ref();
+
+    let old_shuffle = old_playback.and_then(|playback| playback.shuffle_state);
+    if let Some(shuffle_state) = new_playback.and_then(|playback| playback.shuffle_state) {
+        if old_shuffle != Some(shuffle_state) {
+            events.push(Event::ShuffleChanged { shuffle_state });
+        }
+    }
+
+    let old_repeat = old_playback.and_then(|playback| playback.repeat_state.as_ref());
+    if let Some(repeat_state) = new_playback.and_then(|playback| playback.repeat_state.as_ref()) {
+        if old_repeat != Some(repeat_state) {
+            events.push(Event::RepeatChanged { repeat_state: repeat_state.clone() });
+        }
+    }
+
+    let old_context = old.song().and_then(|song| song.context.as_ref());
+    let new_context = new.song().and_then(|song| song.context.as_ref());
+    if old_context.map(|context| &context.uri) != new_context.map(|context| &context.uri) {
+        events.push(Event::ContextChanged { context: new_context.cloned() });
+    }
+
+    // Where the old snapshot would be by now if nothing but playback happened.
+    let expected_progress = old.song_at(new.fetched_at).song.and_then(|song| song.progress_ms);
+    let new_progress = new.song().and_then(|song| song.progress_ms);
+    let jumped = match (expected_progress, new_progress) {
+        (Some(expected), Some(actual)) => expected.abs_diff(actual) > SEEK_TOLERANCE_MS,
+        _ => false,
+    };
+
+    let old_item = old.song().and_then(|song| song.item.as_ref());
+    let new_item = new.song().and_then(|song| song.item.as_ref());
+    let mut track_changed = false;
+    if let Some(new_item) = new_item {
+        let same_track = old_item.is_some_and(|old_item| old_item.uri() == new_item.uri());
+        let replay = same_track && jumped && new_progress.is_some_and(|progress| progress < REPLAY_WINDOW_MS);
+
+        if !same_track || replay {
+            track_changed = true;
+            events.push(Event::TrackChanged { item: Box::new(new_item.clone()), replay });
+        }
+    }
+
+    let old_playing = old.song().and_then(|song| song.is_playing).unwrap_or_default();
+    let new_playing = new.song().and_then(|song| song.is_playing).unwrap_or_default();
+    if old_playing != new_playing {
+        events.push(Event::PlaybackChanged { is_playing: new_playing });
+    }
+
+    if let Some(progress_ms) = new_progress {
+        if jumped && !track_changed {
+            events.push(Event::Seeked { progress_ms });
+        }
+
+        if new_playing {
+            events.push(Event::Progress {
+                progress_ms,
+                duration_ms: new_item.and_then(Item::duration_ms),
+                server_time: new.fetched_at,
+            });
+        }
+    }
+
+    events
+}
+
+/// Logs the interesting events until the bus goes away.
+pub async fn log_events(mut rx: broadcast::Receiver<Event>) {
+    while let Some(event) = next_event(&mut rx).await {
+        match event {
+            Event::TrackChanged { item, replay: false } => info!("Song changed to \"{}\"", item.name()),
+            Event::TrackChanged { item, replay: true } => info!("Playing \"{}\" again", item.name()),
+            Event::PlaybackChanged { is_playing: true } => info!("Playback resumed"),
+            Event::PlaybackChanged { is_playing: false } => info!("Playback paused"),
+            Event::DeviceChanged { device: Some(device) } => info!("Device is online: {}", device.name),
+            Event::DeviceChanged { device: None } => info!("Device went offline"),
+            Event::AuthChanged { logged_in: true, profile } => info!("Logged in to profile \"{}\"", profile),
+            Event::AuthChanged { logged_in: false, profile } => info!("Profile \"{}\" is logged out", profile),
+            _ => {}
+        }
+    }
+}
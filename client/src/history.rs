@@ -0,0 +1,53 @@
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use tokio::sync::broadcast;
+
+use crate::events::{Event, next_event};
+use crate::state::unix_millis;
+
+/// Tracks kept in the history, oldest are dropped first.
+const MAX_ENTRIES: usize = 100;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HistoryEntry {
+    /// Unix time in milliseconds the track started.
+    pub played_at: u64,
+    pub uri: String,
+    pub name: String,
+    pub artists: Vec<String>,
+    pub replay: bool,
+}
+
+/// Tracks played since startup, newest first.
+#[derive(Debug, Default)]
+pub struct History {
+    entries: Mutex<VecDeque<HistoryEntry>>,
+}
+
+impl History {
+    pub fn entries(&self) -> Vec<HistoryEntry> {
+        self.entries.lock().unwrap().iter().cloned().collect()
+    }
+
+    fn push(&self, entry: HistoryEntry) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.push_front(entry);
+        entries.truncate(MAX_ENTRIES);
+    }
+
+    /// Records every track change from `rx` until the bus goes away.
+    pub async fn record(&self, mut rx: broadcast::Receiver<Event>) {
+        while let Some(event) = next_event(&mut rx).await {
+            if let Event::TrackChanged { item, replay } = event {
+                self.push(HistoryEntry {
+                    played_at: unix_millis(),
+                    uri: item.uri().to_string(),
+                    name: item.name().to_string(),
+                    artists: item.artists(),
+                    replay,
+                });
+            }
+        }
+    }
+}
@@ -1,57 +1,315 @@
-use reqwest::{Client};
-use serde::{Deserialize};
-use std::fs::read_to_string;
-use log::{warn, error};
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use log::{info, warn, error};
+
+use credentials::CredentialStore;
+use error::{Error, Result};
+
+pub mod api;
+pub mod compact;
+pub mod config;
+pub mod error;
+pub mod events;
+pub mod history;
+pub mod library;
+pub mod credentials;
+pub mod login;
+pub mod mirror;
+pub mod pkce;
+pub mod scopes;
+pub mod device;
+pub mod playback;
+pub mod playlist;
+pub mod poll;
+pub mod song;
+pub mod state;
+pub mod throttle;
+
+pub const DEFAULT_ACCOUNTS_URL: &str = "https://accounts.spotify.com";
+
+/// How long before the reported expiry we consider a token stale and refresh it.
+const REFRESH_MARGIN: Duration = Duration::from_secs(60);
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct SpotifyReturn {
     pub access_token: Option<String>,
     pub refresh_token: Option<String>,
+    pub expires_in: Option<u64>,
+    pub scope: Option<String>,
+    pub error: Option<String>,
+    pub error_description: Option<String>,
 }
 
-pub async fn get_access_token() -> String {
-    if !std::path::Path::new("refresh_token.txt").exists() {
-        return String::new();
-    }
-
-    let client_id = dotenv::var("SPOTIFY_CLIENT_ID").expect("SPOTIFY_CLIENT_ID not set in .env");
-    let client_secret = dotenv::var("SPOTIFY_CLIENT_SECRET").expect("SPOTIFY_CLIENT_SECRET not set in .env");
-    
-    let buff = String::from(format!("{}:{}", client_id, client_secret));
-    let base64_buff = base64::encode(&buff);
-    
-    let refresh_token = read_to_string("refresh_token.txt").unwrap();
-    
-    let token_request = Client::new()
-        .post("https://accounts.spotify.com/api/token")
-        .header("Authorization", format!("Basic {}", base64_buff))
-        .header("Content-Type", "application/x-www-form-urlencoded")
-        .form(&[
-            ("grant_type", "refresh_token"),
-            ("refresh_token", &refresh_token),
-        ]);
-    
-    let token_response = token_request.send().await.unwrap_or_else(|e| {
-        panic!("Failed sending token request: {}", e)
-    }).text().await.unwrap_or_else(|e| {
-        panic!("Failed getting token response: {}", e)
-    });
+/// How the client authenticates against the Spotify accounts service.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthFlow {
+    /// Authorization code with PKCE, only needs `SPOTIFY_CLIENT_ID`.
+    Pkce,
+    /// Classic authorization code flow using `SPOTIFY_CLIENT_SECRET`.
+    ClientSecret,
+}
+
+impl AuthFlow {
+    /// Reads `SPOTIFY_AUTH_FLOW` (`pkce` or `secret`) from the environment. When it isn't set,
+    /// PKCE is used unless a client secret has been stored.
+    pub fn configured(client_secret: Option<&str>) -> Self {
+        match dotenv::var("SPOTIFY_AUTH_FLOW").unwrap_or_default().to_lowercase().as_str() {
+            "pkce" => AuthFlow::Pkce,
+            "secret" => AuthFlow::ClientSecret,
+            _ => {
+                if client_secret.unwrap_or_default().is_empty() {
+                    AuthFlow::Pkce
+                } else {
+                    AuthFlow::ClientSecret
+                }
+            }
+        }
+    }
+
+    /// Builds a POST to the token endpoint with `params`, authenticated the way this flow requires.
+    pub fn token_request(&self, client: &Client, accounts_url: &str, client_secret: Option<&str>, params: &[(&str, &str)]) -> RequestBuilder {
+        let client_id = dotenv::var("SPOTIFY_CLIENT_ID").expect("SPOTIFY_CLIENT_ID not set in .env");
+
+        let request = client
+            .post(format!("{}/api/token", accounts_url))
+            .header("Content-Type", "application/x-www-form-urlencoded");
+
+        match self {
+            AuthFlow::Pkce => {
+                let mut form = params.to_vec();
+                form.push(("client_id", &client_id));
+                request.form(&form)
+            }
+            AuthFlow::ClientSecret => {
+                let client_secret = client_secret.unwrap_or_default();
+
+                let buff = format!("{}:{}", client_id, client_secret);
+                let base64_buff = base64::encode(&buff);
+
+                request
+                    .header("Authorization", format!("Basic {}", base64_buff))
+                    .form(params)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct CachedToken {
+    profile: String,
+    access_token: String,
+    scopes: Vec<String>,
+    expires_at: Instant,
+}
+
+/// Details about the access token of the selected profile.
+#[derive(Debug, Clone)]
+pub struct TokenStatus {
+    pub scopes: Vec<String>,
+    pub expires_in: Duration,
+}
+
+/// Keeps the current access token in memory and refreshes it shortly before it expires.
+///
+/// One instance is shared between the poller and every actix handler, so the accounts
+/// service is only hit when the token actually needs refreshing. The token always
+/// belongs to the selected profile; switching profiles makes the next call refresh.
+#[derive(Debug)]
+pub struct TokenManager {
+    client: Client,
+    credentials: Arc<CredentialStore>,
+    accounts_url: String,
+    token: Mutex<Option<CachedToken>>,
+}
 
-    if token_response.is_empty() {
-        return String::new();
+impl TokenManager {
+    pub fn new(credentials: Arc<CredentialStore>, accounts_url: &str) -> Self {
+        TokenManager {
+            client: Client::new(),
+            credentials,
+            accounts_url: accounts_url.trim_end_matches('/').to_string(),
+            token: Mutex::new(None),
+        }
     }
 
-    let json: Result<SpotifyReturn, serde_json::Error> = serde_json::from_str(&token_response);
-    if json.is_err() {
-        error!("Failed getting access token, invalid json: {}\nReponse: {}", json.unwrap_err().to_string(), token_response);
-        return String::new();
+    pub fn credentials(&self) -> &CredentialStore {
+        &self.credentials
     }
 
-    let json = json.unwrap();
+    /// Base URL of the accounts service, `https://accounts.spotify.com` unless configured otherwise.
+    pub fn accounts_url(&self) -> &str {
+        &self.accounts_url
+    }
+
+    /// The authorization flow for the stored credentials.
+    pub fn flow(&self) -> AuthFlow {
+        AuthFlow::configured(self.credentials.client_secret().as_deref())
+    }
 
-    if let Some(access_token) = json.access_token {
-        return access_token;
+    /// Returns a valid access token, refreshing it first if it's missing or about to expire.
+    ///
+    /// Fails with `Error::NotLoggedIn` only when the profile has no usable refresh token;
+    /// when the accounts service can't be reached the error says so instead.
+    pub async fn get_access_token(&self) -> Result<String> {
+        Ok(self.token().await?.access_token)
     }
 
-    String::new()
-}
\ No newline at end of file
+    /// Makes sure there's a valid token for the selected profile and describes it.
+    pub async fn status(&self) -> Result<TokenStatus> {
+        let token = self.token().await?;
+        Ok(TokenStatus {
+            scopes: token.scopes,
+            expires_in: token.expires_at.saturating_duration_since(Instant::now()),
+        })
+    }
+
+    async fn token(&self) -> Result<CachedToken> {
+        let mut token = self.token.lock().await;
+
+        let profile = self.credentials.current_profile();
+        if let Some(cached) = token.as_ref() {
+            if cached.profile == profile && Instant::now() + REFRESH_MARGIN < cached.expires_at {
+                return Ok(cached.clone());
+            }
+        }
+
+        *token = None;
+        let refreshed = self.refresh(&profile).await?;
+        *token = Some(refreshed.clone());
+        Ok(refreshed)
+    }
+
+    /// Scopes from `required` that the selected profile hasn't granted. Empty if the
+    /// granted scopes aren't known.
+    pub async fn missing_scopes(&self, required: &[&str]) -> Vec<String> {
+        // Refreshing updates the stored scopes, so make sure that has happened at least once.
+        let _ = self.get_access_token().await;
+
+        match self.credentials.get().granted_scopes {
+            Some(granted) => scopes::missing(&granted, required),
+            None => Vec::new(),
+        }
+    }
+
+    /// Forgets the refresh token and granted scopes of the selected profile.
+    pub async fn logout(&self) -> std::io::Result<()> {
+        self.credentials.update(|c| {
+            c.refresh_token = None;
+            c.granted_scopes = None;
+        })?;
+        self.invalidate().await;
+        Ok(())
+    }
+
+    /// Drops the cached token so the next call to `get_access_token` refreshes it.
+    /// Used when Spotify rejects a token with a 401 before its reported expiry.
+    pub async fn invalidate(&self) {
+        *self.token.lock().await = None;
+    }
+
+    /// Sends the request produced by `build` with a valid access token. If Spotify answers
+    /// 401 the token is refreshed and the request is sent once more.
+    pub async fn send<F>(&self, build: F) -> Result<Response>
+    where
+        F: Fn(&str) -> RequestBuilder,
+    {
+        let access_token = self.get_access_token().await?;
+
+        let response = build(&access_token).send().await?;
+        if response.status() != StatusCode::UNAUTHORIZED {
+            return Ok(response);
+        }
+
+        warn!("Access token was rejected, refreshing...");
+        self.invalidate().await;
+
+        let access_token = self.get_access_token().await?;
+        Ok(build(&access_token).send().await?)
+    }
+
+    /// Trades the refresh token of `profile` for a new access token. Only a refresh token
+    /// the accounts service refuses (`invalid_grant`) is forgotten; transport and server
+    /// failures are returned as they are, so they aren't mistaken for a logout.
+    async fn refresh(&self, profile: &str) -> Result<CachedToken> {
+        let credentials = self.credentials.get_profile(profile).ok_or(Error::NotLoggedIn)?;
+        let refresh_token = credentials.refresh_token.clone().ok_or(Error::NotLoggedIn)?;
+
+        let client_secret = self.credentials.client_secret();
+        let token_request = AuthFlow::configured(client_secret.as_deref()).token_request(&self.client, &self.accounts_url, client_secret.as_deref(), &[
+            ("grant_type", "refresh_token"),
+            ("refresh_token", &refresh_token),
+        ]);
+
+        let response = token_request.send().await.map_err(|e| {
+            error!("Failed sending token request: {}", e);
+            Error::Network(e)
+        })?;
+
+        let status = response.status();
+        let token_response = response.text().await?;
+
+        let json: SpotifyReturn = match serde_json::from_str(&token_response) {
+            Ok(json) => json,
+            Err(e) => {
+                error!("Failed getting access token ({}), invalid json: {}\nReponse: {}", status, e, token_response);
+                return Err(if status.is_success() {
+                    Error::Parse(e)
+                } else {
+                    Error::Spotify { status, message: token_response, reason: None }
+                });
+            }
+        };
+
+        // The refresh token was revoked or has expired, there's no point trying it again.
+        if json.error.as_deref() == Some("invalid_grant") {
+            warn!("Refresh token for profile \"{}\" is no longer valid, please log in again", profile);
+            if let Err(e) = self.credentials.update_profile(profile, |c| c.refresh_token = None) {
+                error!("Failed removing refresh token: {}", e);
+            }
+
+            return Err(Error::NotLoggedIn);
+        }
+
+        if let Some(error) = json.error {
+            error!("Failed refreshing access token ({}): {}", status, error);
+            return Err(Error::Spotify {
+                status,
+                message: json.error_description.unwrap_or_else(|| error.clone()),
+                reason: Some(error),
+            });
+        }
+
+        // Spotify may rotate the refresh token, in which case the old one stops working.
+        if let Some(new_refresh_token) = &json.refresh_token {
+            if *new_refresh_token != refresh_token {
+                match self.credentials.update_profile(profile, |c| c.refresh_token = Some(new_refresh_token.clone())) {
+                    Ok(()) => info!("Stored rotated refresh token"),
+                    Err(e) => error!("Failed storing rotated refresh token: {}", e),
+                }
+            }
+        }
+
+        let access_token = json.access_token.ok_or_else(|| Error::Spotify {
+            status,
+            message: "token response without an access token".to_string(),
+            reason: None,
+        })?;
+        let scopes = scopes::parse(&json.scope.unwrap_or_default());
+        if !scopes.is_empty() && credentials.granted_scopes.as_ref() != Some(&scopes) {
+            if let Err(e) = self.credentials.update_profile(profile, |c| c.granted_scopes = Some(scopes.clone())) {
+                error!("Failed storing granted scopes: {}", e);
+            }
+        }
+        let expires_in = Duration::from_secs(json.expires_in.unwrap_or(3600));
+
+        Ok(CachedToken {
+            profile: profile.to_string(),
+            access_token,
+            scopes,
+            expires_at: Instant::now() + expires_in,
+        })
+    }
+}
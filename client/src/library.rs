@@ -0,0 +1,389 @@
+use log::{info, warn};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+use crate::api::{Page, PageRequest, SpotifyApi};
+use crate::error::{Error, Result};
+use crate::events::{Event, next_event};
+use crate::mirror::Mirror;
+use crate::scopes;
+use crate::state::unix_millis;
+
+/// Most items kept for a single list, the rest is left out and the list marked `truncated`.
+pub const MAX_ITEMS: usize = 10_000;
+/// How long to wait before trying again after a refresh failed.
+const RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// One of the user's lists. Shows and their episodes aren't cached, resume points change
+/// as episodes are played.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LibraryList {
+    Playlists,
+    PlaylistTracks(String),
+    Albums,
+    TopTracks,
+    Shows,
+    ShowEpisodes(String),
+}
+
+impl LibraryList {
+    /// Path of the list in the Web API, relative to its base URL.
+    pub fn path(&self) -> String {
+        match self {
+            LibraryList::Playlists => "/me/playlists".to_string(),
+            LibraryList::PlaylistTracks(id) => format!("/playlists/{}/tracks", id),
+            LibraryList::Albums => "/me/albums".to_string(),
+            LibraryList::TopTracks => "/me/top/tracks".to_string(),
+            LibraryList::Shows => "/me/shows".to_string(),
+            LibraryList::ShowEpisodes(id) => format!("/shows/{}/episodes", id),
+        }
+    }
+
+    /// Page size Spotify uses for the list when none is given.
+    pub fn default_limit(&self) -> u32 {
+        match self {
+            LibraryList::PlaylistTracks(_) => 100,
+            _ => 20,
+        }
+    }
+}
+
+/// Every item of a list as of `fetched_at`.
+#[derive(Debug, Clone)]
+pub struct CachedList {
+    pub items: Arc<Vec<Value>>,
+    /// Spotify's `total`, more than `items.len()` if the list was cut off at `MAX_ITEMS`.
+    pub total: u32,
+    /// Unix time in milliseconds the list was fetched or found unchanged.
+    pub fetched_at: u64,
+    /// For playlist tracks, the playlist's `snapshot_id` they belong to.
+    pub(crate) snapshot_id: Option<String>,
+    /// Loaded from the mirror and not confirmed by Spotify since.
+    pub(crate) from_mirror: bool,
+}
+
+/// How old a cached list is.
+#[derive(Debug, Clone, Serialize)]
+pub struct CacheInfo {
+    pub fetched_at: u64,
+    pub age_ms: u64,
+    /// Older than the TTL, a refresh is due or in progress.
+    pub stale: bool,
+    /// Spotify couldn't be reached to confirm this copy, it's the last one we got.
+    pub offline: bool,
+}
+
+/// Ages of the cached lists, `None` for those not fetched yet.
+#[derive(Debug, Clone, Serialize)]
+pub struct LibraryStatus {
+    pub ttl_secs: u64,
+    pub playlists: Option<CacheInfo>,
+    /// Playlists whose tracks are cached.
+    pub playlist_tracks: usize,
+    pub albums: Option<CacheInfo>,
+    pub top_tracks: Option<CacheInfo>,
+}
+
+#[derive(Debug, Default)]
+struct Lists {
+    /// Profile the lists belong to.
+    profile: String,
+    playlists: Option<CachedList>,
+    tracks: HashMap<String, CachedList>,
+    albums: Option<CachedList>,
+    top_tracks: Option<CachedList>,
+}
+
+/// The user's playlists, their tracks, saved albums and top tracks, kept up to date in
+/// the background by `run` so the library endpoints don't have to wait for Spotify.
+#[derive(Debug)]
+pub struct Library {
+    ttl: Duration,
+    lists: Mutex<Lists>,
+    /// Keeps the playlists and their tracks across restarts.
+    mirror: Option<Arc<Mirror>>,
+    /// The last refresh failed because Spotify couldn't be reached.
+    unreachable: AtomicBool,
+}
+
+impl Library {
+    pub fn new(ttl: Duration, mirror: Option<Mirror>) -> Self {
+        Library {
+            ttl,
+            lists: Mutex::new(Lists::default()),
+            mirror: mirror.map(Arc::new),
+            unreachable: AtomicBool::new(false),
+        }
+    }
+
+    /// The cached `list` of `profile`, even if it's stale. `None` if it hasn't been fetched yet.
+    pub fn get(&self, profile: &str, list: &LibraryList) -> Option<CachedList> {
+        let lists = self.lists.lock().unwrap();
+        if lists.profile != profile {
+            return None;
+        }
+
+        match list {
+            LibraryList::Playlists => lists.playlists.clone(),
+            LibraryList::PlaylistTracks(id) => lists.tracks.get(id).cloned(),
+            LibraryList::Albums => lists.albums.clone(),
+            LibraryList::TopTracks => lists.top_tracks.clone(),
+            LibraryList::Shows | LibraryList::ShowEpisodes(_) => None,
+        }
+    }
+
+    /// Like `get`, but falls back to the mirror for playlists and their tracks. For when
+    /// Spotify can't be reached.
+    pub async fn get_offline(&self, profile: &str, list: &LibraryList) -> Option<CachedList> {
+        if let Some(cached) = self.get(profile, list) {
+            return Some(cached);
+        }
+
+        let profile = profile.to_string();
+        match list.clone() {
+            LibraryList::Playlists => self.mirror(move |mirror| mirror.playlists(&profile)).await?,
+            LibraryList::PlaylistTracks(id) => self.mirror(move |mirror| mirror.tracks(&profile, &id)).await?,
+            _ => None,
+        }
+    }
+
+    /// How old `list` is. It's `offline` if it's being served because a request to Spotify
+    /// just failed, if it came from the mirror and hasn't been refreshed yet, or if the last
+    /// refresh couldn't reach Spotify.
+    pub fn info(&self, list: &CachedList, offline: bool) -> CacheInfo {
+        let age_ms = unix_millis().saturating_sub(list.fetched_at);
+        CacheInfo {
+            fetched_at: list.fetched_at,
+            age_ms,
+            stale: age_ms >= self.ttl.as_millis() as u64,
+            offline: offline || list.from_mirror || self.unreachable.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn status(&self, profile: &str) -> LibraryStatus {
+        let lists = self.lists.lock().unwrap();
+        let current = lists.profile == profile;
+        let info = |list: &Option<CachedList>| list.as_ref().filter(|_| current).map(|list| self.info(list, false));
+
+        LibraryStatus {
+            ttl_secs: self.ttl.as_secs(),
+            playlists: info(&lists.playlists),
+            playlist_tracks: if current { lists.tracks.len() } else { 0 },
+            albums: info(&lists.albums),
+            top_tracks: info(&lists.top_tracks),
+        }
+    }
+
+    fn is_fresh(&self, list: &Option<CachedList>) -> bool {
+        list.as_ref().is_some_and(|list| !self.info(list, false).stale)
+    }
+
+    /// Runs `f` against the mirror on the blocking pool, SQLite would otherwise hold up the
+    /// runtime. `None` if there's no mirror or `f` failed, failures are logged.
+    async fn mirror<T, F>(&self, f: F) -> Option<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Mirror) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let mirror = self.mirror.clone()?;
+        match tokio::task::spawn_blocking(move || f(&mirror)).await {
+            Ok(Ok(value)) => Some(value),
+            Ok(Err(e)) => {
+                warn!("(mirror) {}", e);
+                None
+            }
+            Err(e) => {
+                warn!("(mirror) {}", e);
+                None
+            }
+        }
+    }
+
+    /// The playlists and their tracks of `profile` as last mirrored.
+    async fn load_mirror(&self, profile: &str) -> Lists {
+        let profile = profile.to_string();
+        let loaded = self.mirror({
+            let profile = profile.clone();
+            move |mirror| {
+                let playlists = mirror.playlists(&profile)?;
+                let mut tracks = HashMap::new();
+                if let Some(playlists) = &playlists {
+                    for id in playlists.items.iter().filter_map(|playlist| playlist["id"].as_str()) {
+                        if let Some(list) = mirror.tracks(&profile, id)? {
+                            tracks.insert(id.to_string(), list);
+                        }
+                    }
+                }
+
+                Ok((playlists, tracks))
+            }
+        }).await;
+
+        let (playlists, tracks) = loaded.unwrap_or_default();
+        Lists { profile, playlists, tracks, ..Lists::default() }
+    }
+
+    /// Updates the lists unless the profile changed since `profile` started fetching.
+    fn store(&self, profile: &str, update: impl FnOnce(&mut Lists)) {
+        let mut lists = self.lists.lock().unwrap();
+        if lists.profile == profile {
+            update(&mut lists);
+        }
+    }
+
+    /// Fetches every list that's missing or older than the TTL. Lists the profile lacks
+    /// the scopes for are skipped.
+    pub async fn refresh(&self, api: &SpotifyApi) -> Result<()> {
+        let profile = api.tokens().credentials().current_profile();
+        if self.lists.lock().unwrap().profile != profile {
+            let loaded = self.load_mirror(&profile).await;
+            let mut lists = self.lists.lock().unwrap();
+            if lists.profile != profile {
+                *lists = loaded;
+            }
+        }
+
+        let tokens = api.tokens();
+        if tokens.missing_scopes(&[scopes::PLAYLIST_READ_PRIVATE, scopes::PLAYLIST_READ_COLLABORATIVE]).await.is_empty()
+            && !self.is_fresh(&self.lists.lock().unwrap().playlists)
+        {
+            self.refresh_playlists(api, &profile).await?;
+        }
+
+        if tokens.missing_scopes(&[scopes::USER_LIBRARY_READ]).await.is_empty()
+            && !self.is_fresh(&self.lists.lock().unwrap().albums)
+        {
+            let albums = collect(api, api.albums(&PageRequest { limit: Some(50), offset: None }).await?, None).await?;
+            self.store(&profile, |lists| lists.albums = Some(albums));
+        }
+
+        if tokens.missing_scopes(&[scopes::USER_TOP_READ]).await.is_empty()
+            && !self.is_fresh(&self.lists.lock().unwrap().top_tracks)
+        {
+            let top_tracks = collect(api, api.top_tracks(&PageRequest { limit: Some(50), offset: None }).await?, None).await?;
+            self.store(&profile, |lists| lists.top_tracks = Some(top_tracks));
+        }
+
+        Ok(())
+    }
+
+    /// Refreshes the playlists and the tracks of those whose `snapshot_id` changed.
+    async fn refresh_playlists(&self, api: &SpotifyApi, profile: &str) -> Result<()> {
+        let playlists = collect(api, api.playlists(&PageRequest { limit: Some(50), offset: None }).await?, None).await?;
+
+        let mut tracks = HashMap::new();
+        for playlist in playlists.items.iter() {
+            let id = match playlist["id"].as_str() {
+                Some(id) => id.to_string(),
+                None => continue,
+            };
+            let snapshot_id = playlist["snapshot_id"].as_str().map(String::from);
+
+            let cached = self.lists.lock().unwrap().tracks.get(&id).cloned();
+            let list = match cached {
+                Some(mut cached) if snapshot_id.is_some() && cached.snapshot_id == snapshot_id => {
+                    cached.fetched_at = playlists.fetched_at;
+                    cached.from_mirror = false;
+                    let (mirror_profile, mirror_id, synced_at) = (profile.to_string(), id.clone(), cached.fetched_at);
+                    self.mirror(move |mirror| mirror.touch_tracks(&mirror_profile, &mirror_id, synced_at)).await;
+                    cached
+                }
+                _ => {
+                    let first = api.playlist_tracks(&id, &PageRequest { limit: Some(100), offset: None }).await;
+                    match first {
+                        Ok(first) => {
+                            let tracks = collect(api, first, snapshot_id).await?;
+                            let (mirror_profile, mirror_id, mirrored) = (profile.to_string(), id.clone(), tracks.clone());
+                            self.mirror(move |mirror| mirror.save_tracks(&mirror_profile, &mirror_id, &mirrored)).await;
+                            tracks
+                        }
+                        // A single playlist going missing shouldn't hold up the rest.
+                        Err(e @ Error::Spotify { .. }) => {
+                            warn!("Failed fetching tracks of playlist {}: {}", id, e);
+                            continue;
+                        }
+                        Err(e) => return Err(e),
+                    }
+                }
+            };
+
+            tracks.insert(id, list);
+        }
+
+        info!("Fetched {} playlists", playlists.items.len());
+        let (mirror_profile, mirrored) = (profile.to_string(), playlists.clone());
+        self.mirror(move |mirror| mirror.save_playlists(&mirror_profile, &mirrored)).await;
+        self.store(profile, |lists| {
+            lists.playlists = Some(playlists);
+            lists.tracks = tracks;
+        });
+
+        Ok(())
+    }
+
+    /// Keeps the lists fresh until the event bus goes away, refreshing every TTL and
+    /// right after logging in or switching profiles.
+    pub async fn run(&self, api: &SpotifyApi, mut rx: broadcast::Receiver<Event>) {
+        loop {
+            let result = self.refresh(api).await;
+            self.unreachable.store(result.as_ref().is_err_and(|e| api.is_unreachable(e)), Ordering::Relaxed);
+
+            let wait = match result {
+                Ok(()) => self.ttl,
+                Err(Error::NotLoggedIn) => self.ttl,
+                Err(Error::Throttled { retry_after, .. }) => retry_after,
+                Err(e) => {
+                    warn!("(library) {}", e);
+                    RETRY_DELAY
+                }
+            };
+
+            tokio::select! {
+                _ = tokio::time::sleep(wait) => {}
+                closed = auth_changed(&mut rx) => if closed {
+                    return;
+                },
+            }
+        }
+    }
+}
+
+/// Waits for an `AuthChanged` event. Returns `true` if the bus went away instead.
+async fn auth_changed(rx: &mut broadcast::Receiver<Event>) -> bool {
+    loop {
+        match next_event(rx).await {
+            Some(Event::AuthChanged { .. }) => return false,
+            Some(_) => {}
+            None => return true,
+        }
+    }
+}
+
+/// Follows `first` through every page, up to `MAX_ITEMS` items.
+async fn collect(api: &SpotifyApi, first: Page<Value>, snapshot_id: Option<String>) -> Result<CachedList> {
+    let total = first.total;
+    let mut items = Vec::new();
+    let mut page = Some(first);
+
+    while let Some(current) = page {
+        items.extend(current.items.iter().take(MAX_ITEMS - items.len()).cloned());
+        if items.len() >= MAX_ITEMS {
+            break;
+        }
+
+        page = api.next_page(&current).await?;
+    }
+
+    Ok(CachedList {
+        items: Arc::new(items),
+        total,
+        fetched_at: unix_millis(),
+        snapshot_id,
+        from_mirror: false,
+    })
+}
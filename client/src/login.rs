@@ -0,0 +1,55 @@
+use rand::{distributions::Alphanumeric, Rng};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long a login started at `/login` may take before its `state` is rejected.
+const LOGIN_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// A login that was started but hasn't come back through the callback yet.
+#[derive(Debug, Clone)]
+pub struct LoginAttempt {
+    /// Profile the login was started for.
+    pub profile: String,
+    /// PKCE code verifier, only set when using the PKCE flow.
+    pub code_verifier: Option<String>,
+    started: Instant,
+}
+
+/// Logins in progress keyed by their OAuth `state`, so the callback only accepts
+/// codes for logins this client actually started.
+#[derive(Debug, Default)]
+pub struct PendingLogins {
+    attempts: Mutex<HashMap<String, LoginAttempt>>,
+}
+
+impl PendingLogins {
+    /// Remembers a new login and returns the random `state` to send with the authorize request.
+    pub fn start(&self, profile: &str, code_verifier: Option<String>) -> String {
+        let state: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(32)
+            .map(char::from)
+            .collect();
+
+        let mut attempts = self.attempts.lock().unwrap();
+        attempts.retain(|_, attempt| attempt.started.elapsed() < LOGIN_TTL);
+        attempts.insert(state.clone(), LoginAttempt {
+            profile: profile.to_string(),
+            code_verifier,
+            started: Instant::now(),
+        });
+
+        state
+    }
+
+    /// Removes and returns the login for `state`, or `None` if it's unknown or has expired.
+    pub fn take(&self, state: &str) -> Option<LoginAttempt> {
+        let attempt = self.attempts.lock().unwrap().remove(state)?;
+        if attempt.started.elapsed() >= LOGIN_TTL {
+            return None;
+        }
+
+        Some(attempt)
+    }
+}
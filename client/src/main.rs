@@ -4,154 +4,89 @@ use std::{fs::File, io::Read};
 use std::io::Write;
 
 use actix_web::{get, Responder, HttpResponse, web, HttpServer, App};
-use serde::Deserialize;
-use reqwest::Client;
-use std::fs::read_to_string;
+use actix_web::dev::Service;
+use serde::Serialize;
+use client::{AuthFlow, TokenManager};
+use client::api::SpotifyApi;
+use client::error::{Error, Result};
+use client::config::{Config, LibraryConfig, PollConfig};
+use client::credentials::CredentialStore;
+use client::login::PendingLogins;
+use client::events::{self, Event, EventBus};
+use client::history::History;
+use client::library::Library;
+use client::mirror::Mirror;
+use client::poll::{Activity, next_interval};
+use client::state::{PlayerState, SharedPlayerState, unix_millis};
 use pretty_env_logger::env_logger;
 use std::path::Path;
+use std::sync::Arc;
 
-use log::{info, error};
+use log::{info, warn, error};
 
-mod song;
-mod device;
+mod auth;
 mod spotify;
-
-#[derive(Debug, Deserialize)]
-pub struct AuthRequest {
-    code: String,
+mod profiles;
+mod ws;
+
+#[derive(Debug, Serialize)]
+pub struct EffectiveConfig {
+    host: String,
+    port: u16,
+    base_url: String,
+    redirect_uri: String,
+    headless: bool,
+    export_files: bool,
+    poll: PollConfig,
+    library: LibraryConfig,
 }
 
-#[get("/login")]
-async fn login() -> impl Responder {
-    let scope = String::from("user-read-currently-playing user-modify-playback-state playlist-read-private playlist-read-collaborative user-read-playback-state user-library-read user-modify-playback-state user-top-read");
-    let redirect_uri = String::from("http://localhost:8888/api/v1/callback");
-    let client_id = dotenv::var("SPOTIFY_CLIENT_ID").expect("SPOTIFY_CLIENT_ID not set in .env");
-
-    let redirect_url = format!("https://accounts.spotify.com/authorize?response_type=code&client_id={}&scope={}&redirect_uri={}", client_id, scope, redirect_uri);
-    HttpResponse::Found().append_header(("Location", redirect_url)).finish()
+#[get("/api/v1/config")]
+async fn effective_config(config: web::Data<Config>) -> impl Responder {
+    HttpResponse::Ok().json(EffectiveConfig {
+        host: config.host.clone(),
+        port: config.port,
+        base_url: config.base_url(),
+        redirect_uri: config.redirect_uri(),
+        headless: config.headless,
+        export_files: config.export_files,
+        poll: config.poll.clone(),
+        library: config.library.clone(),
+    })
 }
 
-#[get("/api/v1/callback")]
-async fn callback(info: web::Query<AuthRequest>) -> impl Responder {
-    let code = info.code.clone();
-
-    let client_id = dotenv::var("SPOTIFY_CLIENT_ID").expect("SPOTIFY_CLIENT_ID not set in .env");
-    let client_secret = dotenv::var("SPOTIFY_CLIENT_SECRET").expect("SPOTIFY_CLIENT_SECRET not set in .env");
-    
-    let buff = String::from(format!("{}:{}", client_id, client_secret));
-    let base64_buff = base64::encode(&buff);
-
-    let token_request = Client::new()
-        .post("https://accounts.spotify.com/api/token")
-        .header("Authorization", format!("Basic {}", base64_buff))
-        .header("Content-Type", "application/x-www-form-urlencoded")
-        .form(&[
-            ("grant_type", "authorization_code"),
-            ("code", &code),
-            ("redirect_uri", "http://localhost:8888/api/v1/callback"),
-        ]);
-
-    let token_response = token_request.send().await.unwrap().text().await.unwrap();
-    let json: client::SpotifyReturn = serde_json::from_str(&token_response).unwrap();
+/// Fetches the playback state into `state` with a single request and publishes what changed.
+async fn poll_player(api: &SpotifyApi, state: &SharedPlayerState, events: &EventBus) -> Result<()> {
+    let playback = api.player().await?;
 
-    let refresh_token = json.refresh_token.clone();
+    let previous = state.get();
+    state.set_playback(playback, unix_millis());
+    events.publish_changes(&previous, &state.get());
 
-    use std::fs::File;
-
-    if !Path::new("refresh_token.txt").exists() {
-        File::create("refresh_token.txt").unwrap();
-    }
-
-    if refresh_token.is_some() {
-        let mut file = File::create("refresh_token.txt").unwrap();
-        file.write_all(refresh_token.unwrap().as_bytes()).unwrap();
-    }
-
-    info!("Got refresh token, you can now close the browser window and continue...");
-
-    HttpResponse::Ok().body("Got refresh token, you can now close the browser window and continue...")
+    Ok(())
 }
 
-async fn write_active_song(access_token: &str) {
-    let token_request = Client::new()
-        .get("https://api.spotify.com/v1/me/player/currently-playing")
-        .header("Authorization", format!("Bearer {}", access_token))
-        .header("Content-Type", "application/x-www-form-urlencoded");
-
-    let token_response = token_request
-        .send().await.unwrap_or_else(|e| {
-            panic!("Failed sending request to \"https://api.spotify.com/v1/me/player/currently-playing\": {}", e)
-        }).text().await.unwrap_or_else(|e| {
-            panic!("Failed getting text response from \"https://api.spotify.com/v1/me/player/currently-playing\": {}", e)
-        });
-
-    if token_response.is_empty() {
-        return;
+/// Writes `song.json` and `active_device.json`, removing `song.json` while nothing is
+/// playing. Each file is written to a temporary file first and renamed, so readers never
+/// see a partial file.
+fn export_files(state: &PlayerState) -> Result<()> {
+    match state.song() {
+        Some(song) => write_file("song.json", &serde_json::to_string(song)?)?,
+        None => match std::fs::remove_file("song.json") {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        },
     }
 
-    let result: Result<song::Song, serde_json::Error> = serde_json::from_str(&token_response);
-    if result.is_err() {
-        error!("(write_active_song) Error parsing JSON: {}\n{}", result.unwrap_err(), token_response);
-    } else {
-        let json: song::Song = serde_json::from_str(&token_response).unwrap();
-
-        if !Path::new("song.json").exists() {
-            let mut file = File::create("song.json").unwrap_or_else(|e| {
-                panic!("Error opening song.json: {}", e.to_string())
-            });
-
-            file.write_all(serde_json::to_string(&json).unwrap().as_bytes()).expect("Error writing to song.json");
-        } else {
-            let file_str = read_to_string("song.json").expect("Error reading song.json");
-            let file_json: song::Song = serde_json::from_str(&file_str).expect("Error parsing song.json");
-
-            if json.progress_ms != file_json.progress_ms || json.is_playing != file_json.is_playing {
-                let mut file = File::create("song.json").unwrap_or_else(|e| {
-                    panic!("Error opening song.json: {}", e.to_string())
-                });
-
-                file.write_all(serde_json::to_string(&json).expect("Error writing to song.json").as_bytes()).expect("Error writing to song.json");
+    write_file("active_device.json", &serde_json::to_string(&state.device())?)?;
 
-                if let (Some(item1), Some(item2)) = (json.clone().item, file_json.clone().item) {
-                    if item1.name != item2.name {
-                        info!("Song changed to \"{}\"", item1.name);
-                    }
-                }
-            }
-        }
-    }
+    Ok(())
 }
 
-async fn write_active_device(access_token: &str) -> bool {
-    let client = Client::builder()
-        .user_agent("BeamNG-Spotify")
-        .build().unwrap();
-
-    let response = client
-        .get("https://api.spotify.com/v1/me/player")
-        .header("Authorization", format!("Bearer {}", access_token))
-        .send().await.unwrap().text().await.unwrap();
-
-    if response.is_empty() {
-        return false;
-    }
-
-    let result: Result<device::SpotifyDevice, serde_json::Error> = serde_json::from_str(&response);
-    if result.is_err() {
-        error!("(write_active_device) Error parsing JSON: {}\nReceived:\n{}", result.unwrap_err(), response);
-    } else {
-        let json: device::SpotifyDevice = serde_json::from_str(&response).unwrap();
-
-        let mut file = File::create("active_device.json").unwrap_or_else(|e| {
-            panic!("Error opening active_device.json: {}", e.to_string())
-        });
-    
-        file.write_all(serde_json::to_string(&json).unwrap().as_bytes()).unwrap_or_else(|e| {
-            panic!("Error writing to active_device.json: {}", e.to_string())
-        });
-    }
-
-    return true;
+fn write_file(path: &str, contents: &str) -> std::io::Result<()> {
+    let tmp_path = format!("{}.tmp", path);
+    File::create(&tmp_path)?.write_all(contents.as_bytes())?;
+    std::fs::rename(tmp_path, path)
 }
 
 #[tokio::main]
@@ -160,67 +95,185 @@ async fn main() {
     info!("Starting up...");
 
     if !Path::new(".env").exists() {
-        let mut file = File::create(".env").unwrap();
-        file.write_all("SPOTIFY_CLIENT_ID=\nSPOTIFY_CLIENT_SECRET=".as_bytes()).unwrap();
+        if let Err(e) = File::create(".env").and_then(|mut file| file.write_all("SPOTIFY_CLIENT_ID=\nSPOTIFY_CLIENT_SECRET=\nSPOTIFY_AUTH_FLOW=".as_bytes())) {
+            error!("Failed creating .env file: {}", e);
+            std::process::exit(1);
+        }
+
         info!("Created .env file, please fill in the values
 Steps:
     1. Open https://developer.spotify.com/dashboard/login
     2. Create a new app and give it a name and description, I called mine \"BeamNG-Spotify\"
-    3. Copy the client ID and client secret into the .env file
+    3. Copy the client ID into the .env file
         SPOTIFY_CLIENT_ID=<client_id>
+    4. (Optional) To use the client secret instead of PKCE, also copy it into the .env file
         SPOTIFY_CLIENT_SECRET=<client_secret>
-    4. Run the client again
+        SPOTIFY_AUTH_FLOW=secret
+    5. Run the client again
 ");
         let mut stdin = std::io::stdin();
-        let _ = stdin.read(&mut [0u8]).unwrap();
+        let _ = stdin.read(&mut [0u8]);
         std::process::exit(0);
     }
 
-    let client_id = dotenv::var("SPOTIFY_CLIENT_ID").expect("SPOTIFY_CLIENT_ID not set in .env");
-    let client_secret = dotenv::var("SPOTIFY_CLIENT_SECRET").expect("SPOTIFY_CLIENT_SECRET not set in .env");
+    let credentials = match CredentialStore::open() {
+        Ok(credentials) => Arc::new(credentials),
+        Err(e) => {
+            error!("Failed opening credential store: {}", e);
+            let mut stdin = std::io::stdin();
+            let _ = stdin.read(&mut [0u8]);
+            std::process::exit(1);
+        }
+    };
 
-    if client_id.is_empty() || client_secret.is_empty() {
-        error!("SPOTIFY_CLIENT_ID and SPOTIFY_CLIENT_SECRET must be set in .env file");
+    let client_id = dotenv::var("SPOTIFY_CLIENT_ID").unwrap_or_default();
+    let client_secret = credentials.client_secret();
+    let flow = AuthFlow::configured(client_secret.as_deref());
+
+    if client_id.is_empty() || (flow == AuthFlow::ClientSecret && client_secret.is_none()) {
+        if flow == AuthFlow::ClientSecret {
+            error!("SPOTIFY_CLIENT_ID and SPOTIFY_CLIENT_SECRET must be set in .env file");
+        } else {
+            error!("SPOTIFY_CLIENT_ID must be set in .env file");
+        }
         let mut stdin = std::io::stdin();
-        let _ = stdin.read(&mut [0u8]).unwrap();
+        let _ = stdin.read(&mut [0u8]);
         std::process::exit(0);
     }
 
-    if !Path::new("refresh_token.txt").exists() {
-        info!("Opening browser to get code...");
-        let _ = open::that("http://localhost:8888/login");
-    }
+    info!("Using profile \"{}\" with {:?} authorization flow", credentials.current_profile(), flow);
 
-    let _ = tokio::spawn(async {
-        let mut device_offline = true;
+    let config = web::Data::new(Config::load());
+    info!("Listening on {}, redirect URI is {}", config.base_url(), config.redirect_uri());
 
-        let access_token = client::get_access_token().await;
-        if access_token.is_empty() {
-            error!("Error getting access token, please make sure your .env file is correct.");
-            std::process::exit(1);
+    let needs_login = credentials.get().refresh_token.is_none();
+
+    let tokens = web::Data::new(TokenManager::new(credentials, &config.accounts_url));
+    let api = web::Data::new(SpotifyApi::new(tokens.clone().into_inner(), &config.api_url));
+    let pending_logins = web::Data::new(PendingLogins::default());
+
+    let player_state = web::Data::new(SharedPlayerState::default());
+
+    let activity = web::Data::new(Activity::default());
+    let events = web::Data::new(EventBus::default());
+    let history = web::Data::new(History::default());
+    let mirror = if config.library.mirror {
+        Mirror::open().map_err(|e| error!("Failed opening library mirror: {}", e)).ok()
+    } else {
+        None
+    };
+    let library = web::Data::new(Library::new(config.library.ttl(), mirror));
+
+    tokio::spawn(events::log_events(events.subscribe()));
+
+    let history_recorder = history.clone();
+    let history_events = events.subscribe();
+    tokio::spawn(async move { history_recorder.record(history_events).await });
+
+    if config.library.prefetch {
+        let library_api = api.clone();
+        let library_cache = library.clone();
+        let library_events = events.subscribe();
+        tokio::spawn(async move { library_cache.run(&library_api, library_events).await });
+    }
+
+    let poller_api = api.clone();
+    let poller_state = player_state.clone();
+    let poller_activity = activity.clone();
+    let poller_events = events.clone();
+    let poll_config = config.poll.clone();
+    let export = config.export_files;
+    let login_url = format!("{}/login", config.base_url());
+    tokio::spawn(async move {
+        let mut auth = None;
+        // Progress alone doesn't change the version, so the files are only rewritten when something else did.
+        let mut exported_version = None;
+
+        match poller_api.tokens().status().await {
+            Ok(status) => info!(
+                "Logged in to profile \"{}\", access token expires in {}s",
+                poller_api.tokens().credentials().current_profile(),
+                status.expires_in.as_secs(),
+            ),
+            Err(Error::NotLoggedIn) => warn!("Not logged in, open {} or log in from the game", login_url),
+            Err(e) => warn!("Couldn't refresh the access token, will keep trying: {}", e),
         }
 
-        info!("Access token: {}", access_token);
-
-        loop {        
-            write_active_song(&access_token).await;
-            let new_online = write_active_device(&access_token).await;
-            if !device_offline && !new_online {
-                device_offline = true;
-                info!("Device went offline");
-            } else if device_offline && new_online {
-                device_offline = false;
-                info!("Device is online");
+        loop {
+            if let Some(remaining) = poller_api.throttle().remaining() {
+                tokio::time::sleep(remaining).await;
+                continue;
             }
 
-            tokio::time::sleep(std::time::Duration::from_millis(250)).await;
+            let result = poll_player(&poller_api, &poller_state, &poller_events).await;
+
+            let now_logged_in = !matches!(result, Err(Error::NotLoggedIn));
+            let profile = poller_api.tokens().credentials().current_profile();
+            if auth.as_ref() != Some(&(now_logged_in, profile.clone())) {
+                auth = Some((now_logged_in, profile.clone()));
+                poller_events.publish(Event::AuthChanged {
+                    logged_in: now_logged_in,
+                    profile,
+                });
+
+                if !now_logged_in {
+                    let previous = poller_state.get();
+                    poller_state.set_playback(None, unix_millis());
+                    poller_events.publish_changes(&previous, &poller_state.get());
+                }
+            }
+
+            match result {
+                // Logging out is reported above, throttling by the request layer.
+                Ok(()) | Err(Error::NotLoggedIn) | Err(Error::Throttled { .. }) => {}
+                Err(e) => error!("(poll_player) {}", e),
+            }
+
+            let state = poller_state.get();
+            if export && exported_version != Some(state.version) {
+                match export_files(&state) {
+                    Ok(()) => exported_version = Some(state.version),
+                    Err(e) => error!("(export_files) {}", e),
+                }
+            }
+
+            let interval = next_interval(&poll_config, &poller_state.get(), &poller_activity);
+            tokio::select! {
+                _ = tokio::time::sleep(interval) => {}
+                _ = poller_activity.next_command() => {}
+            }
         }
     });
 
-    HttpServer::new(|| {
+    let login_config = config.clone();
+    let login_tokens = tokens.clone();
+    let login_pending = pending_logins.clone();
+
+    let bind_address = config.bind_address();
+    let server = HttpServer::new(move || {
+        let request_activity = activity.clone();
+
         App::new()
-            .service(callback)
-            .service(login)
+            .wrap_fn(move |req, srv| {
+                request_activity.request();
+                srv.call(req)
+            })
+            .app_data(config.clone())
+            .app_data(tokens.clone())
+            .app_data(api.clone())
+            .app_data(pending_logins.clone())
+            .app_data(player_state.clone())
+            .app_data(activity.clone())
+            .app_data(events.clone())
+            .app_data(history.clone())
+            .app_data(library.clone())
+            .service(effective_config)
+            .service(auth::callback)
+            .service(auth::login)
+            .service(auth::auth_status)
+            .service(auth::start_login)
+            .service(auth::logout)
+            .service(spotify::player_state)
             .service(spotify::current_song)
             .service(spotify::next_song)
             .service(spotify::previous_song)
@@ -233,9 +286,35 @@ Steps:
             .service(spotify::playlist_tracks)
             .service(spotify::albums)
             .service(spotify::top_tracks)
-    }).workers(2).bind("localhost:8888").unwrap_or_else(|e| {
-        panic!("Failed to bind to localhost:8888: {}", e.to_string())
-    }).run().await.unwrap_or_else(|e| {
-        panic!("Failed to run server: {}", e.to_string())
+            .service(spotify::shows)
+            .service(spotify::show_episodes)
+            .service(spotify::resume_point)
+            .service(spotify::throttle)
+            .service(spotify::history)
+            .service(spotify::library_status)
+            .service(ws::ws)
+            .service(profiles::profiles)
+            .service(profiles::switch_profile)
+            .service(profiles::remove_profile)
+    }).workers(2).bind(&bind_address).unwrap_or_else(|e| {
+        error!("Failed to bind to {}: {}", bind_address, e);
+        let mut stdin = std::io::stdin();
+        let _ = stdin.read(&mut [0u8]);
+        std::process::exit(1);
+    }).run();
+
+    // Only once the server is listening, so /login and the callback are there to answer.
+    if needs_login {
+        if login_config.headless {
+            tokio::spawn(async move { auth::headless_login(&login_config, &login_tokens, &login_pending).await });
+        } else {
+            info!("Opening browser to get code...");
+            let _ = open::that(format!("{}/login", login_config.base_url()));
+        }
+    }
+
+    server.await.unwrap_or_else(|e| {
+        error!("Failed to run server: {}", e);
+        std::process::exit(1);
     });
 }
\ No newline at end of file
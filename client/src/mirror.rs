@@ -0,0 +1,150 @@
+use rusqlite::{Connection, OptionalExtension, params};
+use serde_json::Value;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use crate::library::CachedList;
+
+const MIRROR_FILE: &str = "library.db";
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS lists (
+    profile TEXT NOT NULL,
+    list TEXT NOT NULL,
+    snapshot_id TEXT,
+    total INTEGER NOT NULL,
+    synced_at INTEGER NOT NULL,
+    PRIMARY KEY (profile, list)
+);
+CREATE TABLE IF NOT EXISTS items (
+    profile TEXT NOT NULL,
+    list TEXT NOT NULL,
+    position INTEGER NOT NULL,
+    data TEXT NOT NULL,
+    PRIMARY KEY (profile, list, position)
+);
+";
+
+/// Key of the playlist list in the mirror.
+const PLAYLISTS: &str = "playlists";
+
+fn tracks_key(playlist_id: &str) -> String {
+    format!("playlist:{}", playlist_id)
+}
+
+/// Copy of each profile's playlists and their tracks in a SQLite database, so they
+/// survive restarts and can be served while Spotify can't be reached.
+#[derive(Debug)]
+pub struct Mirror {
+    conn: Mutex<Connection>,
+}
+
+impl Mirror {
+    /// Opens `library.db` in the working directory, creating it if needed.
+    pub fn open() -> rusqlite::Result<Self> {
+        Self::open_at(MIRROR_FILE)
+    }
+
+    pub fn open_at<P: AsRef<Path>>(path: P) -> rusqlite::Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+
+        Ok(Mirror {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    pub fn playlists(&self, profile: &str) -> rusqlite::Result<Option<CachedList>> {
+        self.load(profile, PLAYLISTS)
+    }
+
+    pub fn tracks(&self, profile: &str, playlist_id: &str) -> rusqlite::Result<Option<CachedList>> {
+        self.load(profile, &tracks_key(playlist_id))
+    }
+
+    /// Replaces the playlists and drops the tracks of playlists that are gone.
+    pub fn save_playlists(&self, profile: &str, playlists: &CachedList) -> rusqlite::Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        save(&tx, profile, PLAYLISTS, playlists)?;
+
+        let kept: Vec<String> = playlists.items.iter()
+            .filter_map(|playlist| playlist["id"].as_str())
+            .map(tracks_key)
+            .collect();
+
+        let stored: Vec<String> = {
+            let mut statement = tx.prepare("SELECT list FROM lists WHERE profile = ?1 AND list LIKE 'playlist:%'")?;
+            let rows = statement.query_map(params![profile], |row| row.get(0))?;
+            rows.collect::<rusqlite::Result<_>>()?
+        };
+
+        for list in stored.iter().filter(|list| !kept.contains(list)) {
+            tx.execute("DELETE FROM lists WHERE profile = ?1 AND list = ?2", params![profile, list])?;
+            tx.execute("DELETE FROM items WHERE profile = ?1 AND list = ?2", params![profile, list])?;
+        }
+
+        tx.commit()
+    }
+
+    pub fn save_tracks(&self, profile: &str, playlist_id: &str, tracks: &CachedList) -> rusqlite::Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        save(&tx, profile, &tracks_key(playlist_id), tracks)?;
+        tx.commit()
+    }
+
+    /// Records that the mirrored tracks of `playlist_id` were found unchanged at `synced_at`.
+    pub fn touch_tracks(&self, profile: &str, playlist_id: &str, synced_at: u64) -> rusqlite::Result<()> {
+        self.conn.lock().unwrap().execute(
+            "UPDATE lists SET synced_at = ?3 WHERE profile = ?1 AND list = ?2",
+            params![profile, tracks_key(playlist_id), synced_at],
+        )?;
+
+        Ok(())
+    }
+
+    fn load(&self, profile: &str, list: &str) -> rusqlite::Result<Option<CachedList>> {
+        let conn = self.conn.lock().unwrap();
+
+        let header = conn.query_row(
+            "SELECT snapshot_id, total, synced_at FROM lists WHERE profile = ?1 AND list = ?2",
+            params![profile, list],
+            |row| Ok((row.get::<_, Option<String>>(0)?, row.get::<_, u32>(1)?, row.get::<_, u64>(2)?)),
+        ).optional()?;
+
+        let (snapshot_id, total, synced_at) = match header {
+            Some(header) => header,
+            None => return Ok(None),
+        };
+
+        let mut statement = conn.prepare("SELECT data FROM items WHERE profile = ?1 AND list = ?2 ORDER BY position")?;
+        let items = statement
+            .query_map(params![profile, list], |row| row.get::<_, String>(0))?
+            .filter_map(|data| data.map(|data| serde_json::from_str::<Value>(&data).ok()).transpose())
+            .collect::<rusqlite::Result<Vec<Value>>>()?;
+
+        Ok(Some(CachedList {
+            items: Arc::new(items),
+            total,
+            fetched_at: synced_at,
+            snapshot_id,
+            from_mirror: true,
+        }))
+    }
+}
+
+fn save(tx: &rusqlite::Transaction, profile: &str, list: &str, cached: &CachedList) -> rusqlite::Result<()> {
+    tx.execute(
+        "INSERT OR REPLACE INTO lists (profile, list, snapshot_id, total, synced_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![profile, list, cached.snapshot_id, cached.total, cached.fetched_at],
+    )?;
+    tx.execute("DELETE FROM items WHERE profile = ?1 AND list = ?2", params![profile, list])?;
+
+    let mut statement = tx.prepare("INSERT INTO items (profile, list, position, data) VALUES (?1, ?2, ?3, ?4)")?;
+    for (position, item) in cached.items.iter().enumerate() {
+        statement.execute(params![profile, list, position, item.to_string()])?;
+    }
+
+    Ok(())
+}
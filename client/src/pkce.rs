@@ -0,0 +1,17 @@
+use rand::{distributions::Alphanumeric, Rng};
+use sha2::{Digest, Sha256};
+
+/// Generates a random code verifier, 64 characters long (the spec allows 43 to 128).
+pub fn generate_code_verifier() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(64)
+        .map(char::from)
+        .collect()
+}
+
+/// Derives the `S256` code challenge sent with the authorize request.
+pub fn code_challenge(code_verifier: &str) -> String {
+    let hash = Sha256::digest(code_verifier.as_bytes());
+    base64::encode_config(hash, base64::URL_SAFE_NO_PAD)
+}
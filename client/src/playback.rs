@@ -0,0 +1,23 @@
+use serde::{Serialize, Deserialize};
+
+use crate::device::{Device, SpotifyDevice};
+use crate::song::Song;
+
+/// Everything `/me/player` returns: the playing item and progress like `Song`, plus the
+/// device and the shuffle and repeat state.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Playback {
+    #[serde(flatten)]
+    pub song: Song,
+    pub device: Option<Device>,
+    pub shuffle_state: Option<bool>,
+    pub repeat_state: Option<String>,
+}
+
+impl Playback {
+    pub fn device(&self) -> SpotifyDevice {
+        SpotifyDevice {
+            device: self.device.clone(),
+        }
+    }
+}
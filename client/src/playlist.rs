@@ -0,0 +1,42 @@
+use serde::{Serialize, Deserialize};
+
+use crate::song::{Image, Item};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlaylistOwner {
+    pub id: String,
+    pub display_name: Option<String>,
+}
+
+/// Where to get a playlist's tracks and how many there are.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlaylistTracksRef {
+    pub href: Option<String>,
+    pub total: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Playlist {
+    pub id: String,
+    pub uri: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub owner: Option<PlaylistOwner>,
+    /// Spotify sends `null` instead of an empty list for playlists without a cover.
+    #[serde(default)]
+    pub images: Option<Vec<Image>>,
+    pub public: Option<bool>,
+    #[serde(default)]
+    pub collaborative: bool,
+    pub snapshot_id: Option<String>,
+    pub tracks: Option<PlaylistTracksRef>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlaylistTrack {
+    pub added_at: Option<String>,
+    #[serde(default)]
+    pub is_local: bool,
+    /// `None` for tracks that were removed from Spotify.
+    pub track: Option<Item>,
+}
@@ -0,0 +1,113 @@
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+use crate::config::PollConfig;
+use crate::state::PlayerState;
+
+/// How long after a command the poller stays on the fast interval.
+const COMMAND_WINDOW: Duration = Duration::from_secs(3);
+/// Poll fast when the current track ends within this time.
+const TRACK_END_WINDOW: Duration = Duration::from_secs(3);
+/// Without a request from a game client for this long and no open WebSocket, poll on the
+/// idle interval.
+const CLIENT_IDLE: Duration = Duration::from_secs(30);
+
+/// When game clients last talked to us, when they last sent a command and how many
+/// WebSockets are open, so the poller knows how often it has to refresh.
+#[derive(Debug)]
+pub struct Activity {
+    last_request: Mutex<Instant>,
+    last_command: Mutex<Option<Instant>>,
+    command: Notify,
+    sockets: AtomicUsize,
+}
+
+/// An open WebSocket, counted as an active client until it's dropped.
+#[derive(Debug)]
+pub struct Connection<'a> {
+    activity: &'a Activity,
+}
+
+impl Drop for Connection<'_> {
+    fn drop(&mut self) {
+        self.activity.sockets.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Default for Activity {
+    fn default() -> Self {
+        Activity {
+            last_request: Mutex::new(Instant::now()),
+            last_command: Mutex::new(None),
+            command: Notify::new(),
+            sockets: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl Activity {
+    /// Records a request from a game client.
+    pub fn request(&self) {
+        *self.last_request.lock().unwrap() = Instant::now();
+    }
+
+    /// Records a playback command and wakes the poller so the change shows up right away.
+    pub fn command(&self) {
+        *self.last_command.lock().unwrap() = Some(Instant::now());
+        self.command.notify_one();
+    }
+
+    /// Records an open WebSocket. Clients that only listen on it don't send requests,
+    /// so it keeps them counted as active until the returned guard is dropped.
+    pub fn connect(&self) -> Connection<'_> {
+        self.sockets.fetch_add(1, Ordering::Relaxed);
+        Connection { activity: self }
+    }
+
+    /// Resolves on the next command.
+    pub async fn next_command(&self) {
+        self.command.notified().await;
+    }
+
+    /// No open WebSocket and no request for `CLIENT_IDLE`.
+    fn is_idle(&self) -> bool {
+        self.sockets.load(Ordering::Relaxed) == 0 && self.last_request.lock().unwrap().elapsed() > CLIENT_IDLE
+    }
+
+    fn since_command(&self) -> Option<Duration> {
+        self.last_command.lock().unwrap().map(|last_command| last_command.elapsed())
+    }
+}
+
+/// How long the poller waits before its next request.
+pub fn next_interval(config: &PollConfig, state: &PlayerState, activity: &Activity) -> Duration {
+    if activity.since_command().is_some_and(|since| since < COMMAND_WINDOW) {
+        return config.fast();
+    }
+
+    if activity.is_idle() {
+        return config.idle();
+    }
+
+    let song = match state.song() {
+        Some(song) if state.device().device.is_some() => song,
+        _ => return config.idle(),
+    };
+
+    if song.is_playing != Some(true) {
+        return config.paused();
+    }
+
+    let remaining = match (&song.item, song.progress_ms) {
+        (Some(item), Some(progress_ms)) => item.duration_ms().and_then(|duration_ms| (duration_ms as u64).checked_sub(progress_ms)),
+        _ => None,
+    };
+
+    if remaining.is_some_and(|remaining| Duration::from_millis(remaining) < TRACK_END_WINDOW) {
+        return config.fast();
+    }
+
+    config.playing()
+}
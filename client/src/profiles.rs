@@ -0,0 +1,65 @@
+use log::info;
+use serde::Serialize;
+use actix_web::{get, post, delete, Responder, HttpResponse, web};
+
+use client::TokenManager;
+use client::error::{Error, Result};
+
+#[derive(Debug, Serialize)]
+pub struct ProfileInfo {
+    pub name: String,
+    pub logged_in: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProfileList {
+    pub current: String,
+    pub profiles: Vec<ProfileInfo>,
+}
+
+fn profile_list(tokens: &TokenManager) -> ProfileList {
+    let credentials = tokens.credentials();
+
+    ProfileList {
+        current: credentials.current_profile(),
+        profiles: credentials.profiles().into_iter().map(|name| {
+            let logged_in = credentials.get_profile(&name).and_then(|c| c.refresh_token).is_some();
+            ProfileInfo { name, logged_in }
+        }).collect(),
+    }
+}
+
+#[get("/api/v1/profiles")]
+async fn profiles(tokens: web::Data<TokenManager>) -> impl Responder {
+    HttpResponse::Ok().json(profile_list(&tokens))
+}
+
+/// Selects a profile, creating it if it doesn't exist. A new profile has to log in
+/// through `/login` before it can be used.
+#[post("/api/v1/profiles/{name}")]
+async fn switch_profile(tokens: web::Data<TokenManager>, name: web::Path<String>) -> Result<HttpResponse> {
+    let name = name.into_inner();
+    if name.trim().is_empty() {
+        return Err(Error::BadRequest("Profile name can't be empty".to_string()));
+    }
+
+    tokens.credentials().switch_profile(&name)?;
+
+    tokens.invalidate().await;
+    info!("Switched to profile \"{}\"", name);
+
+    Ok(HttpResponse::Ok().json(profile_list(&tokens)))
+}
+
+#[delete("/api/v1/profiles/{name}")]
+async fn remove_profile(tokens: web::Data<TokenManager>, name: web::Path<String>) -> Result<HttpResponse> {
+    match tokens.credentials().remove_profile(&name) {
+        Ok(true) => {
+            info!("Removed profile \"{}\"", name);
+            Ok(HttpResponse::Ok().json(profile_list(&tokens)))
+        }
+        Ok(false) => Err(Error::NotFound(format!("No profile named \"{}\"", name))),
+        Err(e) if e.kind() == std::io::ErrorKind::InvalidInput => Err(Error::Conflict(e.to_string())),
+        Err(e) => Err(e.into()),
+    }
+}
@@ -0,0 +1,46 @@
+pub const USER_READ_CURRENTLY_PLAYING: &str = "user-read-currently-playing";
+pub const USER_READ_PLAYBACK_STATE: &str = "user-read-playback-state";
+pub const USER_MODIFY_PLAYBACK_STATE: &str = "user-modify-playback-state";
+pub const PLAYLIST_READ_PRIVATE: &str = "playlist-read-private";
+pub const PLAYLIST_READ_COLLABORATIVE: &str = "playlist-read-collaborative";
+pub const USER_LIBRARY_READ: &str = "user-library-read";
+pub const USER_TOP_READ: &str = "user-top-read";
+pub const USER_READ_PLAYBACK_POSITION: &str = "user-read-playback-position";
+
+/// Every scope this build of the client asks for when logging in.
+pub const ALL: &[&str] = &[
+    USER_READ_CURRENTLY_PLAYING,
+    USER_READ_PLAYBACK_STATE,
+    USER_MODIFY_PLAYBACK_STATE,
+    PLAYLIST_READ_PRIVATE,
+    PLAYLIST_READ_COLLABORATIVE,
+    USER_LIBRARY_READ,
+    USER_TOP_READ,
+    USER_READ_PLAYBACK_POSITION,
+];
+
+/// Splits a space separated `scope` value as returned by the token endpoint.
+pub fn parse(scope: &str) -> Vec<String> {
+    scope.split_whitespace().map(String::from).collect()
+}
+
+/// Scopes from `required` that aren't in `granted`.
+pub fn missing(granted: &[String], required: &[&str]) -> Vec<String> {
+    required
+        .iter()
+        .filter(|scope| !granted.iter().any(|g| g == *scope))
+        .map(|scope| scope.to_string())
+        .collect()
+}
+
+/// All scopes in `a` and `b`, without duplicates, keeping the order they first appear in.
+pub fn union<A: AsRef<str>, B: AsRef<str>>(a: &[A], b: &[B]) -> Vec<String> {
+    let mut scopes: Vec<String> = Vec::new();
+    for scope in a.iter().map(AsRef::as_ref).chain(b.iter().map(AsRef::as_ref)) {
+        if !scopes.iter().any(|s| s == scope) {
+            scopes.push(scope.to_string());
+        }
+    }
+
+    scopes
+}
@@ -1,73 +1,225 @@
 use std::collections::HashMap;
-use serde::{Serialize, Deserialize};
+use serde::{Serialize, Deserialize, Deserializer};
+use serde_json::Value;
+
+// Spotify leaves fields out or sends `null` for local files, episodes and ads, so
+// anything that isn't always there is optional or has a default.
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Image {
-    pub height: u32,
+    /// `None` for images Spotify doesn't know the size of, like playlist covers.
+    pub height: Option<u32>,
     pub url: String,
-    pub width: u32,
+    pub width: Option<u32>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Album {
-    pub album_type: String,
+    pub album_type: Option<String>,
     pub artists: Vec<Artist>,
     pub available_markets: Vec<String>,
     pub external_urls: HashMap<String, String>,
-    pub href: String,
+    pub href: Option<String>,
+    pub id: Option<String>,
     pub images: Vec<Image>,
     pub name: String,
-    pub release_date: String,
-    pub release_date_precision: String,
-    pub total_tracks: u32,
+    pub release_date: Option<String>,
+    pub release_date_precision: Option<String>,
+    pub total_tracks: Option<u32>,
     #[serde(rename = "type")]
     pub type_: String,
-    pub uri: String,
+    pub uri: Option<String>,
 }
 
+/// An album in the user's library.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedAlbum {
+    pub added_at: Option<String>,
+    pub album: Album,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ExternalUrls {
-    pub spotify: String,
+    pub spotify: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Artist {
     pub external_urls: ExternalUrls,
-    pub href: String,
-    pub id: String,
+    pub href: Option<String>,
+    pub id: Option<String>,
     pub name: String,
     #[serde(rename = "type")]
     pub type_: String,
-    pub uri: String
+    pub uri: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Artists {
-    pub items: Vec<Artist>,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Item {
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Track {
     pub album: Album,
+    pub artists: Vec<Artist>,
     pub available_markets: Vec<String>,
-    pub disc_number: u32,
+    pub disc_number: Option<u32>,
     pub duration_ms: u32,
     pub explicit: bool,
     pub external_ids: HashMap<String, String>,
     pub external_urls: HashMap<String, String>,
-    pub href: String,
-    pub id: String,
+    pub href: Option<String>,
+    /// `None` for local files.
+    pub id: Option<String>,
     pub is_local: bool,
     pub name: String,
-    pub popularity: u32,
+    pub popularity: Option<u32>,
     pub preview_url: Option<String>,
-    pub track_number: u32,
+    pub track_number: Option<u32>,
     #[serde(rename = "type")]
     pub type_: String,
     pub uri: String,
 }
 
+/// The podcast an episode belongs to.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Show {
+    pub description: Option<String>,
+    pub external_urls: HashMap<String, String>,
+    pub href: Option<String>,
+    pub id: Option<String>,
+    pub images: Vec<Image>,
+    pub name: String,
+    pub publisher: Option<String>,
+    pub total_episodes: Option<u32>,
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub uri: Option<String>,
+}
+
+/// A show the user follows.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedShow {
+    pub added_at: Option<String>,
+    pub show: Show,
+}
+
+/// How far the user got into an episode. Needs the `user-read-playback-position` scope.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ResumePoint {
+    pub fully_played: bool,
+    pub resume_position_ms: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Episode {
+    pub description: Option<String>,
+    pub duration_ms: u32,
+    pub explicit: bool,
+    pub external_urls: HashMap<String, String>,
+    pub href: Option<String>,
+    pub id: Option<String>,
+    pub images: Vec<Image>,
+    pub name: String,
+    pub release_date: Option<String>,
+    pub resume_point: Option<ResumePoint>,
+    /// Left out when listing a show's episodes.
+    pub show: Option<Show>,
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub uri: String,
+}
+
+/// Whatever is playing. Serialized as the object Spotify sent.
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum Item {
+    Track(Track),
+    Episode(Episode),
+    /// Something we don't have a model for, or that didn't fit one, kept as is.
+    Unknown(Value),
+}
+
+impl Item {
+    /// Parses `value` as the kind of item `kind` says it is (`currently_playing_type`), or
+    /// by its own `type` if `kind` isn't known. Falls back to `Unknown` instead of failing.
+    pub fn parse(kind: Option<&str>, value: Value) -> Item {
+        let kind = kind
+            .filter(|kind| *kind != "unknown")
+            .or_else(|| value["type"].as_str())
+            .unwrap_or_default()
+            .to_string();
+
+        let parsed = match kind.as_str() {
+            "track" => serde_json::from_value(value.clone()).map(Item::Track).ok(),
+            "episode" => serde_json::from_value(value.clone()).map(Item::Episode).ok(),
+            _ => None,
+        };
+
+        parsed.unwrap_or(Item::Unknown(value))
+    }
+
+    pub fn id(&self) -> Option<&str> {
+        match self {
+            Item::Track(track) => track.id.as_deref(),
+            Item::Episode(episode) => episode.id.as_deref(),
+            Item::Unknown(value) => value["id"].as_str(),
+        }
+    }
+
+    pub fn uri(&self) -> &str {
+        match self {
+            Item::Track(track) => &track.uri,
+            Item::Episode(episode) => &episode.uri,
+            Item::Unknown(value) => value["uri"].as_str().unwrap_or_default(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Item::Track(track) => &track.name,
+            Item::Episode(episode) => &episode.name,
+            Item::Unknown(value) => value["name"].as_str().unwrap_or_default(),
+        }
+    }
+
+    pub fn duration_ms(&self) -> Option<u32> {
+        match self {
+            Item::Track(track) => Some(track.duration_ms),
+            Item::Episode(episode) => Some(episode.duration_ms),
+            Item::Unknown(value) => value["duration_ms"].as_u64().map(|duration_ms| duration_ms as u32),
+        }
+    }
+
+    /// The track's artists, or the show an episode is from.
+    pub fn artists(&self) -> Vec<String> {
+        match self {
+            Item::Track(track) => track.artists.iter().map(|artist| artist.name.clone()).collect(),
+            Item::Episode(episode) => episode.show.iter().map(|show| show.name.clone()).collect(),
+            Item::Unknown(_) => Vec::new(),
+        }
+    }
+
+    /// The album cover or episode image in every size Spotify has.
+    pub fn images(&self) -> &[Image] {
+        match self {
+            Item::Track(track) => &track.album.images,
+            Item::Episode(episode) => &episode.images,
+            Item::Unknown(_) => &[],
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Item {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Item::parse(None, Value::deserialize(deserializer)?))
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Disallows {
     pub resuming: Option<bool>,
     pub toggling_repeat_context: Option<bool>,
@@ -76,27 +228,59 @@ pub struct Disallows {
     pub skipping_prev: Option<bool>
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Actions {
+    #[serde(default)]
     pub disallows: Disallows,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Context {
+    #[serde(default)]
     pub external_urls: HashMap<String, String>,
-    pub href: String,
+    pub href: Option<String>,
     #[serde(rename = "type")]
     pub type_: String,
     pub uri: String,
 }
 
+/// `Song` as Spotify sends it, before `item` is parsed according to `currently_playing_type`.
+#[derive(Debug, Deserialize)]
+struct RawSong {
+    timestamp: Option<u64>,
+    context: Option<Context>,
+    progress_ms: Option<u64>,
+    item: Option<Value>,
+    currently_playing_type: Option<String>,
+    actions: Option<Actions>,
+    is_playing: Option<bool>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "RawSong")]
 pub struct Song {
     pub timestamp: Option<u64>,
     pub context: Option<Context>,
     pub progress_ms: Option<u64>,
     pub item: Option<Item>,
+    /// `track`, `episode`, `ad` or `unknown`.
     pub currently_playing_type: Option<String>,
     pub actions: Option<Actions>,
     pub is_playing: Option<bool>,
-}
\ No newline at end of file
+}
+
+impl From<RawSong> for Song {
+    fn from(raw: RawSong) -> Self {
+        Song {
+            item: raw.item
+                .filter(|item| !item.is_null())
+                .map(|item| Item::parse(raw.currently_playing_type.as_deref(), item)),
+            timestamp: raw.timestamp,
+            context: raw.context,
+            progress_ms: raw.progress_ms,
+            currently_playing_type: raw.currently_playing_type,
+            actions: raw.actions,
+            is_playing: raw.is_playing,
+        }
+    }
+}
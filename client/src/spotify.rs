@@ -1,265 +1,503 @@
 use log::info;
+use actix_web::{get, post, HttpResponse, web};
 use serde::{Serialize, Deserialize};
-use reqwest::Client;
-use actix_web::{get, post, Responder, HttpResponse, web};
-use std::fs::read_to_string;
+use std::convert::Infallible;
+use std::time::Duration;
+
+use client::{TokenManager, scopes};
+use client::api::{Page, PageRequest, SpotifyApi, SpotifyPlay};
+use client::error::{Error, ErrorBody, Result};
+use client::compact::{CompactItem, compact, compact_page};
+use client::history::History;
+use client::library::{CacheInfo, CachedList, Library, LibraryList, MAX_ITEMS};
+use client::poll::Activity;
+use client::device::SpotifyDevice;
+use client::state::{CurrentSong, SharedPlayerState, unix_millis};
+
+/// Fails with `not_logged_in` if the selected profile is logged out, so nothing cached
+/// for it is served, and with `scope_missing` if it lacks any of `required`.
+async fn require_scopes(tokens: &TokenManager, required: &[&str]) -> Result<()> {
+    if tokens.credentials().get().refresh_token.is_none() {
+        return Err(Error::NotLoggedIn);
+    }
 
-use crate::device;
+    let missing_scopes = tokens.missing_scopes(required).await;
+    if missing_scopes.is_empty() {
+        return Ok(());
+    }
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct SpotifyOffset {
-    pub position: u32,
+    Err(Error::ScopeMissing(missing_scopes))
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct SpotifyPlay {
-    pub uris: Option<Vec<String>>,
-    pub context_uri: Option<String>,
-    pub offset: Option<SpotifyOffset>,
-    pub position_ms: Option<u32>
+/// The last polled song with its progress extrapolated to now. Only has `server_time`
+/// if nothing is playing.
+fn song_response(state: &SharedPlayerState) -> HttpResponse {
+    HttpResponse::Ok().json(state.get().song_at(unix_millis()))
 }
 
-#[get("/api/v1/current_song")]
-async fn current_song() -> impl Responder {
-    let current_song = read_to_string("song.json").unwrap();
-    HttpResponse::Ok().body(current_song)
+/// Longest a `/api/v1/state` request may wait for a change.
+const MAX_STATE_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Deserialize)]
+pub struct StateQuery {
+    /// Version the client already has.
+    since: Option<u64>,
+    /// Milliseconds to wait for a newer version.
+    timeout: Option<u64>,
 }
 
-#[post("/api/v1/next_song")]
-async fn next_song() -> impl Responder {
-    let access_token = client::get_access_token().await;
-    if access_token.is_empty() {
-        return HttpResponse::Ok().body("No access token");
-    }
+#[derive(Debug, Serialize)]
+pub struct StateResponse {
+    pub version: u64,
+    /// Whether `version` differs from `since`.
+    pub changed: bool,
+    pub song: CurrentSong,
+    pub device: SpotifyDevice,
+}
 
-    let client = Client::builder()
-        .user_agent("BeamNG-Spotify")
-        .build().unwrap();
+/// Returns the player state right away if its version differs from `since`, otherwise
+/// waits up to `timeout` milliseconds for it to change.
+#[get("/api/v1/state")]
+async fn player_state(state: web::Data<SharedPlayerState>, query: web::Query<StateQuery>) -> Result<HttpResponse> {
+    let current = match query.since {
+        Some(since) => {
+            let timeout = Duration::from_millis(query.timeout.unwrap_or_default()).min(MAX_STATE_TIMEOUT);
+            state.wait_for_change(since, timeout).await
+        }
+        None => state.get(),
+    };
 
-    client
-        .post("https://api.spotify.com/v1/me/player/next")
-        .header("Authorization", format!("Bearer {}", access_token))
-        .header("Content-Type", "application/json")
-        .header("Content-Length", "0")
-        .send().await.unwrap().text().await.unwrap();
+    Ok(HttpResponse::Ok().json(StateResponse {
+        version: current.version,
+        changed: query.since != Some(current.version),
+        song: current.song_at(unix_millis()),
+        device: current.device(),
+    }))
+}
 
-    HttpResponse::Ok().finish()
+#[get("/api/v1/current_song")]
+async fn current_song(state: web::Data<SharedPlayerState>) -> Result<HttpResponse> {
+    Ok(song_response(&state))
 }
 
-#[post("/api/v1/previous_song")]
-async fn previous_song() -> impl Responder {
-    let access_token = client::get_access_token().await;
-    if access_token.is_empty() {
-        return HttpResponse::Ok().body("No access token");
-    }
+#[derive(Debug, Deserialize)]
+pub struct LibraryQuery {
+    limit: Option<u32>,
+    offset: Option<u32>,
+    /// Follow `next` links and return every item instead of a single page.
+    #[serde(default)]
+    all: bool,
+    /// Ask Spotify even if the library cache has the list.
+    #[serde(default)]
+    fresh: bool,
+    /// Only return what the in-game UI shows of each item, see `compact::CompactItem`.
+    #[serde(default)]
+    compact: bool,
+}
 
-    let client = Client::builder()
-        .user_agent("BeamNG-Spotify")
-        .build().unwrap();
+impl LibraryQuery {
+    /// The first page to fetch. When getting everything, pages are as big as Spotify
+    /// allows (`max_limit`) unless asked for smaller ones.
+    fn page(&self, max_limit: u32) -> PageRequest {
+        PageRequest {
+            limit: if self.all { Some(self.limit.map_or(max_limit, |limit| limit.min(max_limit))) } else { self.limit },
+            offset: self.offset,
+        }
+    }
+}
 
-    client
-        .post("https://api.spotify.com/v1/me/player/previous")
-        .header("Authorization", format!("Bearer {}", access_token))
-        .header("Content-Length", "0")
-        .send().await.unwrap().text().await.unwrap();
+/// A page, or with `all=true` everything from `offset` on, served from the library cache.
+#[derive(Debug, Serialize)]
+struct CachedPage<T: Serialize> {
+    items: T,
+    total: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    limit: Option<u32>,
+    offset: u32,
+    /// Left out with `all=true`, like in the merged list from Spotify.
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    links: Option<PageLinks>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    truncated: Option<bool>,
+    cache: CacheInfo,
+}
 
-    let yes = read_to_string("song.json").unwrap();
-    HttpResponse::Ok().body(yes)
+/// Spotify's paging links, built the way Spotify builds them so a cached page can be
+/// followed like one fetched from Spotify.
+#[derive(Debug, Serialize)]
+struct PageLinks {
+    href: String,
+    next: Option<String>,
+    previous: Option<String>,
 }
 
-#[post("/api/v1/play_song")]
-async fn play(body: web::Json<SpotifyPlay>) -> impl Responder {
-    let access_token = client::get_access_token().await;
-    if access_token.is_empty() {
-        return HttpResponse::Ok().body("No access token");
+impl PageLinks {
+    fn new(api: &SpotifyApi, list: &LibraryList, offset: u32, limit: u32, total: u32) -> Self {
+        let link = |offset: u32| format!("{}{}?offset={}&limit={}", api.base_url(), list.path(), offset, limit);
+        PageLinks {
+            href: link(offset),
+            next: (offset.saturating_add(limit) < total).then(|| link(offset + limit)),
+            previous: (offset > 0).then(|| link(offset.saturating_sub(limit))),
+        }
     }
+}
 
-    let client = Client::builder()
-        .user_agent("BeamNG-Spotify")
-        .build().unwrap();
-
-    let json = serde_json::to_string(&body).unwrap();
-
-    client
-        .put("https://api.spotify.com/v1/me/player/play")
-        .header("Authorization", format!("Bearer {}", access_token))
-        .header("Content-Type", "application/json")
-        .header("Content-Length", format!("{}", json.len()))
-        .body(json)
-        .send().await.unwrap().text().await.unwrap();
+/// Answers from the library cache if it has `list` for the selected profile, even a stale
+/// one, unless the query asks for `fresh` data.
+fn cached_response(api: &SpotifyApi, library: &Library, query: &LibraryQuery, list: &LibraryList) -> Option<HttpResponse> {
+    if query.fresh {
+        return None;
+    }
 
-    HttpResponse::Ok().finish()
+    let cached = library.get(&api.tokens().credentials().current_profile(), list)?;
+    Some(page_response(api, library, query, list, &cached, false))
 }
 
-#[post("/api/v1/pause_song")]
-async fn pause() -> impl Responder {
-    let access_token = client::get_access_token().await;
-    if access_token.is_empty() {
-        return HttpResponse::Ok().body("No access token");
+/// Serves the last copy of `list` if `error` means Spotify couldn't be reached, the
+/// response's `cache` says it's `offline`. Otherwise fails with `error`.
+async fn offline_response(api: &SpotifyApi, library: &Library, query: &LibraryQuery, list: &LibraryList, error: Error) -> Result<HttpResponse> {
+    if api.is_unreachable(&error) {
+        if let Some(cached) = library.get_offline(&api.tokens().credentials().current_profile(), list).await {
+            return Ok(page_response(api, library, query, list, &cached, true));
+        }
     }
 
-    let client = Client::builder()
-        .user_agent("BeamNG-Spotify")
-        .build().unwrap();
-        
+    Err(error)
+}
 
-    client
-        .put("https://api.spotify.com/v1/me/player/pause")
-        .header("Authorization", format!("Bearer {}", access_token))
-        .header("Content-Length", "0")
-        .send().await.unwrap().text().await.unwrap();
+/// The part of `cached` the query asks for, marked `offline` if it stands in for Spotify's answer.
+fn page_response(api: &SpotifyApi, library: &Library, query: &LibraryQuery, list: &LibraryList, cached: &CachedList, offline: bool) -> HttpResponse {
+    let offset = query.offset.unwrap_or_default();
+    let items = cached.items.get(offset as usize..).unwrap_or_default();
+    let (items, limit, links) = if query.all {
+        (items, None, None)
+    } else {
+        let limit = query.limit.unwrap_or_else(|| list.default_limit());
+        let links = PageLinks::new(api, list, offset, limit, cached.total);
+        (&items[..items.len().min(limit as usize)], Some(limit), Some(links))
+    };
 
-    HttpResponse::Ok().finish()
+    let truncated = query.all.then(|| (offset as usize + items.len()) < cached.total as usize);
+    let cache = library.info(cached, offline);
+    if query.compact {
+        let items: Vec<CompactItem> = items.iter().filter_map(|item| compact(list, item)).collect();
+        HttpResponse::Ok().json(CachedPage { items, total: cached.total, limit, offset, links, truncated, cache })
+    } else {
+        HttpResponse::Ok().json(CachedPage { items, total: cached.total, limit, offset, links, truncated, cache })
+    }
 }
 
-#[post("/api/v1/seek/{position_ms}")]
-async fn seek(position_ms: web::Path<u32>) -> impl Responder {
-    let access_token = client::get_access_token().await;
-    if access_token.is_empty() {
-        return HttpResponse::Ok().body("No access token");
+/// `item` as JSON, made compact if the query asks for it. `None` if it's left out.
+fn item_json(as_compact: bool, list: &LibraryList, item: &serde_json::Value) -> Option<String> {
+    if as_compact {
+        compact(list, item).and_then(|item| serde_json::to_string(&item).ok())
+    } else {
+        Some(item.to_string())
     }
+}
 
-    let position_ms = position_ms;
+/// The page as Spotify returned it, or with `all=true` every page from there on merged
+/// into `{"items": [...], "total": n, "truncated": false}`.
+///
+/// The merged list is streamed as pages arrive. A page failing halfway through can't
+/// change the status anymore, so it ends the list with `truncated` and an `error`.
+fn library_response(api: web::Data<SpotifyApi>, query: &LibraryQuery, list: LibraryList, first: Page<serde_json::Value>) -> HttpResponse {
+    if !query.all {
+        return if query.compact {
+            HttpResponse::Ok().json(compact_page(&list, first))
+        } else {
+            HttpResponse::Ok().json(first)
+        };
+    }
 
-    let client = Client::builder()
-        .user_agent("BeamNG-Spotify")
-        .build().unwrap();
+    struct AllPages {
+        api: web::Data<SpotifyApi>,
+        list: LibraryList,
+        compact: bool,
+        page: Option<Page<serde_json::Value>>,
+        total: u32,
+        expected: usize,
+        /// Items read from Spotify and how many of them were written out.
+        sent: usize,
+        written: usize,
+        error: Option<ErrorBody>,
+        started: bool,
+        finished: bool,
+    }
 
-    client
-        .put(format!("https://api.spotify.com/v1/me/player/seek/?position_ms={}", position_ms))
-        .header("Authorization", format!("Bearer {}", access_token))
-        .header("Content-Length", "0")
-        .send().await.unwrap().text().await.unwrap();
+    let state = AllPages {
+        api,
+        list,
+        compact: query.compact,
+        total: first.total,
+        expected: first.total.saturating_sub(first.offset) as usize,
+        page: Some(first),
+        sent: 0,
+        written: 0,
+        error: None,
+        started: false,
+        finished: false,
+    };
 
-    let position_ms_i64 = position_ms.into_inner() as i64;
+    let stream = futures_util::stream::unfold(state, |mut state| async move {
+        if state.finished {
+            return None;
+        }
+
+        let mut chunk = String::new();
+        if !state.started {
+            chunk.push_str("{\"items\":[");
+            state.started = true;
+        }
+
+        match state.page.take() {
+            Some(page) => {
+                for item in page.items.iter().take(MAX_ITEMS - state.sent) {
+                    state.sent += 1;
+                    let json = match item_json(state.compact, &state.list, item) {
+                        Some(json) => json,
+                        None => continue,
+                    };
+
+                    if state.written > 0 {
+                        chunk.push(',');
+                    }
+
+                    chunk.push_str(&json);
+                    state.written += 1;
+                }
+
+                if state.sent < MAX_ITEMS {
+                    match state.api.next_page(&page).await {
+                        Ok(next) => state.page = next,
+                        Err(e) => state.error = Some(e.body()),
+                    }
+                }
+            }
+            None => {
+                chunk.push_str(&format!("],\"total\":{},\"truncated\":{}", state.total, state.sent < state.expected));
+                if let Some(error) = &state.error {
+                    chunk.push_str(&format!(",\"error\":{}", serde_json::to_string(error).unwrap_or_default()));
+                }
+
+                chunk.push('}');
+                state.finished = true;
+            }
+        }
+
+        Some((Ok::<_, Infallible>(web::Bytes::from(chunk)), state))
+    });
+
+    HttpResponse::Ok().content_type("application/json").streaming(stream)
+}
 
-    let mins: i64 = position_ms_i64 / 1000 / 60;
-    let secs: i64 = (position_ms_i64 / 1000) % 60;
+/// A playback command, sent to one of the endpoints below or as JSON over `/api/v1/ws`,
+/// e.g. `{"command": "seek", "position_ms": 1000}`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Command {
+    Next,
+    Previous,
+    Play(SpotifyPlay),
+    Pause,
+    Seek { position_ms: u32 },
+    Volume { volume_percent: u32 },
+}
 
-    let min_str = if mins < 10 {
-        format!("0{}", mins)
-    } else {
-        format!("{}", mins)
-    };
+impl Command {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Next => "next",
+            Command::Previous => "previous",
+            Command::Play(_) => "play",
+            Command::Pause => "pause",
+            Command::Seek { .. } => "seek",
+            Command::Volume { .. } => "volume",
+        }
+    }
+}
 
-    let sec_str = if secs < 10 {
-        format!("0{}", secs)
-    } else {
-        format!("{}", secs)
+/// `body` starting at the resume point of the episode it starts with. Anything that
+/// isn't an episode, or has been played to the end, starts at `position_ms` as usual.
+async fn resume_episode(api: &SpotifyApi, body: &SpotifyPlay) -> Result<SpotifyPlay> {
+    let mut body = body.clone();
+    let episode_id = match body.start_uri().and_then(|uri| uri.strip_prefix("spotify:episode:")) {
+        Some(episode_id) => episode_id.to_string(),
+        None => return Ok(body),
     };
 
-    let time = format!("{}:{}", min_str, sec_str);
+    require_scopes(api.tokens(), &[scopes::USER_READ_PLAYBACK_POSITION]).await?;
 
-    info!("Set time to: {}", time);
+    if let Some(point) = api.episode(&episode_id).await?.resume_point {
+        if !point.fully_played {
+            body.position_ms = Some(point.resume_position_ms as u32);
+        }
+    }
 
-    HttpResponse::Ok().finish()
+    Ok(body)
 }
 
-#[post("/api/v1/volume/{volume}")]
-async fn volume(volume: web::Path<u32>) -> impl Responder {
-    let access_token = client::get_access_token().await;
-    if access_token.is_empty() {
-        return HttpResponse::Ok().body("No access token");
+/// Sends `command` to Spotify and has the poller pick up the result right away.
+pub async fn run_command(api: &SpotifyApi, activity: &Activity, command: &Command) -> Result<()> {
+    require_scopes(api.tokens(), &[scopes::USER_MODIFY_PLAYBACK_STATE]).await?;
+
+    match command {
+        Command::Next => api.next().await?,
+        Command::Previous => api.previous().await?,
+        Command::Play(body) if body.resume => api.play(&resume_episode(api, body).await?).await?,
+        Command::Play(body) => api.play(body).await?,
+        Command::Pause => api.pause().await?,
+        Command::Seek { position_ms } => {
+            api.seek(*position_ms).await?;
+
+            let mins = position_ms / 1000 / 60;
+            let secs = (position_ms / 1000) % 60;
+            info!("Set time to: {:02}:{:02}", mins, secs);
+        }
+        Command::Volume { volume_percent } => {
+            api.volume(*volume_percent).await?;
+            info!("Set volume to: {}", volume_percent);
+        }
     }
 
-    let volume = volume;
+    activity.command();
+    Ok(())
+}
 
-    let client = Client::builder()
-        .user_agent("BeamNG-Spotify")
-        .build().unwrap();
+#[post("/api/v1/next_song")]
+async fn next_song(api: web::Data<SpotifyApi>, activity: web::Data<Activity>) -> Result<HttpResponse> {
+    run_command(&api, &activity, &Command::Next).await?;
+    Ok(HttpResponse::Ok().finish())
+}
 
-    client
-        .put(format!("https://api.spotify.com/v1/me/player/volume/?volume_percent={}", volume))
-        .header("Authorization", format!("Bearer {}", access_token))
-        .header("Content-Length", "0")
-        .send().await.unwrap().text().await.unwrap();
+#[post("/api/v1/previous_song")]
+async fn previous_song(api: web::Data<SpotifyApi>, activity: web::Data<Activity>, state: web::Data<SharedPlayerState>) -> Result<HttpResponse> {
+    run_command(&api, &activity, &Command::Previous).await?;
+    Ok(song_response(&state))
+}
 
-    info!("Set volume to: {}", volume);
+#[post("/api/v1/play_song")]
+async fn play(api: web::Data<SpotifyApi>, activity: web::Data<Activity>, body: web::Json<SpotifyPlay>) -> Result<HttpResponse> {
+    run_command(&api, &activity, &Command::Play(body.into_inner())).await?;
+    Ok(HttpResponse::Ok().finish())
+}
 
-    HttpResponse::Ok().finish()
+#[post("/api/v1/pause_song")]
+async fn pause(api: web::Data<SpotifyApi>, activity: web::Data<Activity>) -> Result<HttpResponse> {
+    run_command(&api, &activity, &Command::Pause).await?;
+    Ok(HttpResponse::Ok().finish())
 }
 
-#[get("/api/v1/playlists")]
-async fn playlists() -> impl Responder {
-    let access_token = client::get_access_token().await;
-    if access_token.is_empty() {
-        return HttpResponse::Ok().body("No access token");
-    }
+#[post("/api/v1/seek/{position_ms}")]
+async fn seek(api: web::Data<SpotifyApi>, activity: web::Data<Activity>, position_ms: web::Path<u32>) -> Result<HttpResponse> {
+    run_command(&api, &activity, &Command::Seek { position_ms: position_ms.into_inner() }).await?;
+    Ok(HttpResponse::Ok().finish())
+}
 
-    let client = Client::builder()
-        .user_agent("BeamNG-Spotify")
-        .build().unwrap();
+#[post("/api/v1/volume/{volume}")]
+async fn volume(api: web::Data<SpotifyApi>, activity: web::Data<Activity>, volume: web::Path<u32>) -> Result<HttpResponse> {
+    run_command(&api, &activity, &Command::Volume { volume_percent: volume.into_inner() }).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[get("/api/v1/playlists")]
+async fn playlists(api: web::Data<SpotifyApi>, library: web::Data<Library>, query: web::Query<LibraryQuery>) -> Result<HttpResponse> {
+    require_scopes(api.tokens(), &[scopes::PLAYLIST_READ_PRIVATE, scopes::PLAYLIST_READ_COLLABORATIVE]).await?;
 
-    let response = client
-        .get("https://api.spotify.com/v1/me/playlists")
-        .header("Authorization", format!("Bearer {}", access_token))
-        .send().await.unwrap();
+    if let Some(response) = cached_response(&api, &library, &query, &LibraryList::Playlists) {
+        return Ok(response);
+    }
 
-    HttpResponse::Ok().body(response.text().await.unwrap())
+    let first = match api.playlists(&query.page(50)).await {
+        Ok(first) => first,
+        Err(e) => return offline_response(&api, &library, &query, &LibraryList::Playlists, e).await,
+    };
+    Ok(library_response(api, &query, LibraryList::Playlists, first))
 }
-// todo: make a new thread for playlist and tracks, update every 30 seconds.
+
 #[get("/api/v1/playlists/{playlist_id}/tracks")]
-async fn playlist_tracks(playlist_id: web::Path<String>) -> impl Responder {
-    let access_token = client::get_access_token().await;
-    if access_token.is_empty() {
-        return HttpResponse::Ok().body("No access token");
+async fn playlist_tracks(api: web::Data<SpotifyApi>, library: web::Data<Library>, playlist_id: web::Path<String>, query: web::Query<LibraryQuery>) -> Result<HttpResponse> {
+    require_scopes(api.tokens(), &[scopes::PLAYLIST_READ_PRIVATE, scopes::PLAYLIST_READ_COLLABORATIVE]).await?;
+
+    let list = LibraryList::PlaylistTracks(playlist_id.to_string());
+    if let Some(response) = cached_response(&api, &library, &query, &list) {
+        return Ok(response);
     }
 
-    let client = Client::builder()
-        .user_agent("BeamNG-Spotify")
-        .build().unwrap();
+    let first = match api.playlist_tracks(&playlist_id, &query.page(100)).await {
+        Ok(first) => first,
+        Err(e) => return offline_response(&api, &library, &query, &list, e).await,
+    };
+    Ok(library_response(api, &query, list, first))
+}
 
-    let response = client
-        .get(format!("https://api.spotify.com/v1/playlists/{}/tracks", playlist_id))
-        .header("Authorization", format!("Bearer {}", access_token))
-        .send().await.unwrap();
+#[get("/api/v1/albums")]
+async fn albums(api: web::Data<SpotifyApi>, library: web::Data<Library>, query: web::Query<LibraryQuery>) -> Result<HttpResponse> {
+    require_scopes(api.tokens(), &[scopes::USER_LIBRARY_READ]).await?;
 
-    HttpResponse::Ok().body(response.text().await.unwrap())
+    if let Some(response) = cached_response(&api, &library, &query, &LibraryList::Albums) {
+        return Ok(response);
+    }
+
+    let first = api.albums(&query.page(50)).await?;
+    Ok(library_response(api, &query, LibraryList::Albums, first))
 }
 
-#[get("/api/v1/albums")]
-async fn albums() -> impl Responder {
-    let access_token = client::get_access_token().await;
-    if access_token.is_empty() {
-        return HttpResponse::Ok().body("No access token");
+#[get("/api/v1/top_tracks")]
+async fn top_tracks(api: web::Data<SpotifyApi>, library: web::Data<Library>, query: web::Query<LibraryQuery>) -> Result<HttpResponse> {
+    require_scopes(api.tokens(), &[scopes::USER_TOP_READ]).await?;
+
+    if let Some(response) = cached_response(&api, &library, &query, &LibraryList::TopTracks) {
+        return Ok(response);
     }
 
-    let client = Client::builder()
-        .user_agent("BeamNG-Spotify")
-        .build().unwrap();
+    let first = api.top_tracks(&query.page(50)).await?;
+    Ok(library_response(api, &query, LibraryList::TopTracks, first))
+}
+
+#[get("/api/v1/active_device")]
+async fn active_device(state: web::Data<SharedPlayerState>) -> Result<HttpResponse> {
+    Ok(HttpResponse::Ok().json(state.get().device()))
+}
+
+#[get("/api/v1/throttle")]
+async fn throttle(api: web::Data<SpotifyApi>) -> Result<HttpResponse> {
+    Ok(HttpResponse::Ok().json(api.throttle().status()))
+}
 
-    let response = client
-        .get("https://api.spotify.com/v1/me/albums")
-        .header("Authorization", format!("Bearer {}", access_token))
-        .send().await.unwrap();
+#[get("/api/v1/history")]
+async fn history(history: web::Data<History>) -> Result<HttpResponse> {
+    Ok(HttpResponse::Ok().json(history.entries()))
+}
 
-    HttpResponse::Ok().body(response.text().await.unwrap())
+/// How old each cached library list is.
+#[get("/api/v1/library")]
+async fn library_status(api: web::Data<SpotifyApi>, library: web::Data<Library>) -> Result<HttpResponse> {
+    Ok(HttpResponse::Ok().json(library.status(&api.tokens().credentials().current_profile())))
 }
 
-#[get("/api/v1/top_tracks")]
-async fn top_tracks() -> impl Responder {
-    let access_token = client::get_access_token().await;
-    if access_token.is_empty() {
-        return HttpResponse::Ok().body("No access token");
-    }
+#[get("/api/v1/shows")]
+async fn shows(api: web::Data<SpotifyApi>, query: web::Query<LibraryQuery>) -> Result<HttpResponse> {
+    require_scopes(api.tokens(), &[scopes::USER_LIBRARY_READ]).await?;
 
-    let client = Client::builder()
-        .user_agent("BeamNG-Spotify")
-        .build().unwrap();
+    let first = api.shows(&query.page(50)).await?;
+    Ok(library_response(api, &query, LibraryList::Shows, first))
+}
 
-    let response = client
-        .get("https://api.spotify.com/v1/me/top/tracks")
-        .header("Authorization", format!("Bearer {}", access_token))
-        .send().await.unwrap();
+/// A show's episodes, newest first, with their resume points.
+#[get("/api/v1/shows/{show_id}/episodes")]
+async fn show_episodes(api: web::Data<SpotifyApi>, show_id: web::Path<String>, query: web::Query<LibraryQuery>) -> Result<HttpResponse> {
+    require_scopes(api.tokens(), &[scopes::USER_READ_PLAYBACK_POSITION]).await?;
 
-    HttpResponse::Ok().body(response.text().await.unwrap())
+    let first = api.show_episodes(&show_id, &query.page(50)).await?;
+    Ok(library_response(api, &query, LibraryList::ShowEpisodes(show_id.into_inner()), first))
 }
 
-#[get("/api/v1/active_device")]
-async fn active_device() -> impl Responder {
-    if let Some(active_device) = read_to_string("active_device.json").ok() {
-        return HttpResponse::Ok().body(active_device);
-    }
+/// Where the user left off in an episode.
+#[get("/api/v1/episodes/{episode_id}/resume_point")]
+async fn resume_point(api: web::Data<SpotifyApi>, episode_id: web::Path<String>) -> Result<HttpResponse> {
+    require_scopes(api.tokens(), &[scopes::USER_READ_PLAYBACK_POSITION]).await?;
 
-    HttpResponse::Ok().body(serde_json::to_string(&device::SpotifyDevice::default()).unwrap())
-}
\ No newline at end of file
+    let episode = api.episode(&episode_id).await?;
+    Ok(HttpResponse::Ok().json(episode.resume_point.unwrap_or_default()))
+}
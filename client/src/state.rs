@@ -0,0 +1,144 @@
+use serde::Serialize;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::watch;
+
+use crate::device::SpotifyDevice;
+use crate::playback::Playback;
+use crate::song::{Item, Song};
+
+/// A playing track's progress may drift this far from the extrapolated value before
+/// it's treated as a seek.
+pub(crate) const SEEK_TOLERANCE_MS: u64 = 2000;
+
+/// Playback state as last seen by the poller.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PlayerState {
+    /// `None` if there's no active device.
+    pub playback: Option<Playback>,
+    /// Unix time in milliseconds when `playback` was fetched.
+    pub fetched_at: u64,
+    /// Increased whenever the state changes in a way extrapolation can't predict:
+    /// anything other than progress advancing while playing.
+    pub version: u64,
+}
+
+/// The current song with its progress brought up to `server_time`.
+#[derive(Debug, Clone, Serialize)]
+pub struct CurrentSong {
+    #[serde(flatten)]
+    pub song: Option<Song>,
+    /// Unix time in milliseconds the progress was calculated for.
+    pub server_time: u64,
+}
+
+impl PlayerState {
+    /// The currently playing song, `None` if nothing is playing.
+    pub fn song(&self) -> Option<&Song> {
+        self.playback.as_ref().map(|playback| &playback.song)
+    }
+
+    pub fn device(&self) -> SpotifyDevice {
+        self.playback.as_ref().map(Playback::device).unwrap_or_default()
+    }
+
+    /// The current song with `progress_ms` extrapolated from when it was fetched to `now`
+    /// (Unix milliseconds) if it's playing, clamped to the track's duration.
+    ///
+    /// Uses the time we fetched the snapshot rather than Spotify's `timestamp`, which is
+    /// when playback last changed, not when the progress was measured.
+    pub fn song_at(&self, now: u64) -> CurrentSong {
+        let song = self.song().cloned().map(|mut song| {
+            if let (Some(true), Some(progress_ms)) = (song.is_playing, song.progress_ms) {
+                let mut progress_ms = progress_ms + now.saturating_sub(self.fetched_at);
+                if let Some(duration_ms) = song.item.as_ref().and_then(Item::duration_ms) {
+                    progress_ms = progress_ms.min(duration_ms as u64);
+                }
+
+                song.progress_ms = Some(progress_ms);
+            }
+
+            song
+        });
+
+        CurrentSong { song, server_time: now }
+    }
+
+    /// Whether replacing this state with `playback` fetched at `fetched_at` is a change
+    /// clients need to hear about.
+    fn changes_to(&self, playback: &Option<Playback>, fetched_at: u64) -> bool {
+        if without_progress(&self.playback) != without_progress(playback) {
+            return true;
+        }
+
+        let expected = self.song_at(fetched_at).song.and_then(|song| song.progress_ms);
+        let actual = playback.as_ref().and_then(|playback| playback.song.progress_ms);
+        match (expected, actual) {
+            (Some(expected), Some(actual)) => expected.abs_diff(actual) > SEEK_TOLERANCE_MS,
+            (expected, actual) => expected != actual,
+        }
+    }
+}
+
+/// `PlayerState` shared between the poller, which updates it, and the handlers.
+#[derive(Debug)]
+pub struct SharedPlayerState {
+    tx: watch::Sender<PlayerState>,
+}
+
+impl Default for SharedPlayerState {
+    fn default() -> Self {
+        SharedPlayerState {
+            tx: watch::Sender::new(PlayerState::default()),
+        }
+    }
+}
+
+impl SharedPlayerState {
+    pub fn get(&self) -> PlayerState {
+        self.tx.borrow().clone()
+    }
+
+    /// Stores a freshly polled snapshot, bumping the version if it changed.
+    pub fn set_playback(&self, playback: Option<Playback>, fetched_at: u64) {
+        self.tx.send_modify(|state| {
+            if state.changes_to(&playback, fetched_at) {
+                state.version += 1;
+            }
+
+            state.playback = playback;
+            state.fetched_at = fetched_at;
+        });
+    }
+
+    /// Waits up to `timeout` for the version to differ from `since` and returns the state,
+    /// changed or not.
+    pub async fn wait_for_change(&self, since: u64, timeout: Duration) -> PlayerState {
+        let mut rx = self.tx.subscribe();
+        let _ = tokio::time::timeout(timeout, rx.wait_for(|state| state.version != since)).await;
+        self.get()
+    }
+
+    /// A receiver that's notified on every update.
+    pub fn subscribe(&self) -> watch::Receiver<PlayerState> {
+        self.tx.subscribe()
+    }
+}
+
+/// Current Unix time in milliseconds.
+pub fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// `playback` as JSON without the fields that change on every poll.
+fn without_progress(playback: &Option<Playback>) -> serde_json::Value {
+    let mut value = serde_json::to_value(playback).unwrap_or_default();
+    if let Some(object) = value.as_object_mut() {
+        object.remove("progress_ms");
+        object.remove("timestamp");
+    }
+
+    value
+}
@@ -0,0 +1,111 @@
+use serde::Serialize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Used when a `429` comes without a usable `Retry-After` header.
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(1);
+/// First backoff after a `5xx`, doubled for each one in a row up to `MAX_BACKOFF`.
+const BASE_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// Commands arriving this close to the end of a window wait for it instead of failing.
+pub const MAX_QUEUE_WAIT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ThrottleReason {
+    /// Spotify answered `429 Too Many Requests`.
+    RateLimited,
+    /// Backing off after server errors.
+    ServerError,
+}
+
+#[derive(Debug, Default)]
+struct ThrottleState {
+    until: Option<Instant>,
+    reason: Option<ThrottleReason>,
+    server_errors: u32,
+    rate_limit_hits: u64,
+}
+
+/// Current throttling, as served by `/api/v1/throttle`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ThrottleStatus {
+    pub throttled: bool,
+    pub reason: Option<ThrottleReason>,
+    /// Milliseconds until requests are sent again.
+    pub retry_after_ms: Option<u64>,
+    /// Server errors in a row, reset by the next successful request.
+    pub server_errors: u32,
+    /// `429` responses since startup.
+    pub rate_limit_hits: u64,
+}
+
+/// Tracks when Spotify may be called again after a `429` or a run of `5xx` responses.
+///
+/// Shared by every request `SpotifyApi` makes, so the poller and the handlers all
+/// stay quiet until the window passes.
+#[derive(Debug, Default)]
+pub struct Throttle {
+    state: Mutex<ThrottleState>,
+}
+
+impl Throttle {
+    /// Time left until requests may be sent again, `None` if they may be sent now.
+    pub fn remaining(&self) -> Option<Duration> {
+        self.window().map(|(_, remaining)| remaining)
+    }
+
+    /// Why requests are held back and the time left, `None` if they may be sent now.
+    pub fn window(&self) -> Option<(ThrottleReason, Duration)> {
+        let state = self.state.lock().unwrap();
+        let remaining = state.until
+            .map(|until| until.saturating_duration_since(Instant::now()))
+            .filter(|remaining| !remaining.is_zero());
+
+        state.reason.zip(remaining)
+    }
+
+    /// Records a `429` with its `Retry-After` value in seconds.
+    pub fn rate_limited(&self, retry_after: Option<u64>) -> Duration {
+        let retry_after = retry_after.map(Duration::from_secs).unwrap_or(DEFAULT_RETRY_AFTER);
+
+        let mut state = self.state.lock().unwrap();
+        state.until = Some(Instant::now() + retry_after);
+        state.reason = Some(ThrottleReason::RateLimited);
+        state.rate_limit_hits += 1;
+
+        retry_after
+    }
+
+    /// Records a `5xx` and returns how long to back off.
+    pub fn server_error(&self) -> Duration {
+        let mut state = self.state.lock().unwrap();
+        state.server_errors += 1;
+
+        let backoff = BASE_BACKOFF
+            .saturating_mul(2u32.saturating_pow(state.server_errors - 1))
+            .min(MAX_BACKOFF);
+        state.until = Some(Instant::now() + backoff);
+        state.reason = Some(ThrottleReason::ServerError);
+
+        backoff
+    }
+
+    pub fn success(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.server_errors = 0;
+    }
+
+    pub fn status(&self) -> ThrottleStatus {
+        let remaining = self.remaining();
+        let state = self.state.lock().unwrap();
+
+        ThrottleStatus {
+            throttled: remaining.is_some(),
+            reason: remaining.and(state.reason),
+            retry_after_ms: remaining.map(|remaining| remaining.as_millis() as u64),
+            server_errors: state.server_errors,
+            rate_limit_hits: state.rate_limit_hits,
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use std::net::IpAddr;
+
+use actix_web::{get, HttpRequest, HttpResponse, web};
+use actix_web::http::header;
+use actix_ws::{Message, Session};
+use log::{info, warn};
+use reqwest::Url;
+use serde::Serialize;
+use tokio::sync::broadcast::error::RecvError;
+
+use client::api::SpotifyApi;
+use client::config::Config;
+use client::error::{Error, ErrorBody};
+use client::events::{self, Event, EventBus};
+use client::poll::Activity;
+use client::state::{PlayerState, SharedPlayerState};
+
+use crate::spotify::{Command, run_command};
+
+/// Replies to a command sent over the socket.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Reply {
+    CommandResult {
+        command: &'static str,
+    },
+    Error {
+        #[serde(flatten)]
+        error: ErrorBody,
+    },
+}
+
+async fn send<T: Serialize>(session: &mut Session, message: &T) -> bool {
+    match serde_json::to_string(message) {
+        Ok(text) => session.text(text).await.is_ok(),
+        Err(_) => true,
+    }
+}
+
+async fn handle_command(api: &SpotifyApi, activity: &Activity, text: &str) -> Reply {
+    let command: Command = match serde_json::from_str(text) {
+        Ok(command) => command,
+        Err(e) => return Reply::Error {
+            error: Error::BadRequest(format!("Invalid command: {}", e)).body(),
+        },
+    };
+
+    match run_command(api, activity, &command).await {
+        Ok(()) => Reply::CommandResult { command: command.name() },
+        Err(e) => Reply::Error { error: e.body() },
+    }
+}
+
+/// Whether a handshake with this `Origin` may connect. Browsers send one on every
+/// WebSocket handshake, so any web page could otherwise drive the player. Pages on this
+/// server or on localhost are fine, and so are non-web origins like the game UI's.
+fn allowed_origin(config: &Config, origin: &str) -> bool {
+    let Ok(origin) = Url::parse(origin) else {
+        return false;
+    };
+
+    if !matches!(origin.scheme(), "http" | "https") {
+        return true;
+    }
+
+    let is_loopback = origin.host_str().is_some_and(|host| {
+        host.eq_ignore_ascii_case("localhost")
+            || host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback())
+    });
+
+    is_loopback || Url::parse(&config.base_url()).is_ok_and(|base_url| base_url.origin() == origin.origin())
+}
+
+/// Pushes events from the poller as JSON text messages and accepts the same commands as
+/// the REST endpoints, e.g. `{"command": "next"}`.
+///
+/// New connections first get events describing the current state. Handshakes from other
+/// web pages are refused, see `allowed_origin`.
+#[get("/api/v1/ws")]
+async fn ws(
+    req: HttpRequest,
+    body: web::Payload,
+    config: web::Data<Config>,
+    api: web::Data<SpotifyApi>,
+    activity: web::Data<Activity>,
+    events: web::Data<EventBus>,
+    state: web::Data<SharedPlayerState>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if let Some(origin) = req.headers().get(header::ORIGIN) {
+        let origin = origin.to_str().unwrap_or_default();
+        if !allowed_origin(&config, origin) {
+            warn!("Refused WebSocket connection from origin {}", origin);
+            return Err(Error::Forbidden(format!("Origin {} may not connect", origin)).into());
+        }
+    }
+
+    let (response, mut session, mut stream) = actix_ws::handle(&req, body)?;
+    let mut rx = events.subscribe();
+
+    actix_web::rt::spawn(async move {
+        info!("WebSocket client connected");
+        let _connection = activity.connect();
+
+        let auth = Event::AuthChanged {
+            logged_in: !matches!(api.tokens().status().await, Err(Error::NotLoggedIn)),
+            profile: api.tokens().credentials().current_profile(),
+        };
+
+        if !send(&mut session, &auth).await {
+            return;
+        }
+
+        for event in events::diff(&PlayerState::default(), &state.get()) {
+            if !send(&mut session, &event).await {
+                return;
+            }
+        }
+
+        loop {
+            tokio::select! {
+                event = rx.recv() => match event {
+                    Ok(event) => {
+                        if !send(&mut session, &event).await {
+                            break;
+                        }
+                    }
+                    Err(RecvError::Lagged(missed)) => warn!("WebSocket client fell behind, skipped {} events", missed),
+                    Err(RecvError::Closed) => break,
+                },
+                message = stream.recv() => match message {
+                    Some(Ok(Message::Text(text))) => {
+                        let reply = handle_command(&api, &activity, &text).await;
+                        if !send(&mut session, &reply).await {
+                            break;
+                        }
+                    }
+                    Some(Ok(Message::Ping(bytes))) => {
+                        if session.pong(&bytes).await.is_err() {
+                            break;
+                        }
+                    }
+                    Some(Ok(Message::Close(reason))) => {
+                        let _ = session.close(reason).await;
+                        info!("WebSocket client disconnected");
+                        return;
+                    }
+                    Some(Ok(_)) => {}
+                    Some(Err(_)) | None => break,
+                },
+            }
+        }
+
+        let _ = session.close(None).await;
+        info!("WebSocket client disconnected");
+    });
+
+    Ok(response)
+}
@@ -0,0 +1,175 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer, ResponseError};
+use reqwest::StatusCode;
+use serde_json::json;
+
+use client::TokenManager;
+use client::api::SpotifyApi;
+use client::credentials::CredentialStore;
+use client::error::Error;
+use client::song::Item;
+use client::throttle::ThrottleReason;
+
+/// Tokens the mock server handed out and the requests it saw, as `METHOD uri token`.
+#[derive(Debug, Default)]
+struct Mock {
+    tokens_issued: usize,
+    requests: Vec<String>,
+    /// Makes the token endpoint fail with this status instead of handing out a token.
+    token_failure: Option<u16>,
+}
+
+async fn mock_spotify(req: HttpRequest, mock: web::Data<Mutex<Mock>>) -> HttpResponse {
+    let mut mock = mock.lock().unwrap();
+    let token = req.headers()
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .unwrap_or_default()
+        .to_string();
+    mock.requests.push(format!("{} {} {}", req.method(), req.uri(), token));
+
+    match req.path() {
+        "/api/token" => match mock.token_failure {
+            Some(400) => HttpResponse::BadRequest().json(json!({
+                "error": "invalid_grant",
+                "error_description": "Refresh token revoked",
+            })),
+            Some(status) => HttpResponse::build(StatusCode::from_u16(status).unwrap()).body("upstream unavailable"),
+            None => {
+                mock.tokens_issued += 1;
+                HttpResponse::Ok().json(json!({
+                    "access_token": format!("token-{}", mock.tokens_issued),
+                    "token_type": "Bearer",
+                    "expires_in": 3600,
+                    "scope": "user-read-playback-state user-modify-playback-state",
+                }))
+            }
+        },
+        // The first token is rejected as if it expired early.
+        _ if token == "token-1" => HttpResponse::Unauthorized().json(json!({
+            "error": { "status": 401, "message": "The access token expired" },
+        })),
+        "/v1/me" => HttpResponse::Ok().json(json!({ "id": "driver", "display_name": "Driver" })),
+        "/v1/me/player" => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(include_str!("fixtures/player_track.json")),
+        "/v1/me/player/next" => HttpResponse::NotFound().json(json!({
+            "error": { "status": 404, "message": "Player command failed: No active device found", "reason": "NO_ACTIVE_DEVICE" },
+        })),
+        _ => HttpResponse::NotFound().finish(),
+    }
+}
+
+/// Starts the mock server and an API client pointed at it, logged in to a fresh profile.
+async fn mock_api(name: &str) -> (SpotifyApi, web::Data<Mutex<Mock>>) {
+    std::env::set_var("SPOTIFY_CLIENT_ID", "client-id");
+    std::env::set_var("SPOTIFY_CREDENTIALS_PASSPHRASE", "test passphrase");
+
+    let mock = web::Data::new(Mutex::new(Mock::default()));
+    let server_mock = mock.clone();
+    let server = HttpServer::new(move || App::new().app_data(server_mock.clone()).default_service(web::to(mock_spotify)))
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+    let url = format!("http://{}", server.addrs()[0]);
+    actix_web::rt::spawn(server.run());
+
+    // A directory of its own, so the store doesn't import anything left next to it.
+    let dir = std::env::temp_dir().join(format!("beamng-spotify-api-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let credentials = CredentialStore::open_at(dir.join("credentials.enc")).unwrap();
+    credentials.update(|c| c.refresh_token = Some("refresh-token".to_string())).unwrap();
+
+    let tokens = Arc::new(TokenManager::new(Arc::new(credentials), &url));
+    (SpotifyApi::new(tokens, &format!("{}/v1", url)), mock)
+}
+
+#[actix_web::test]
+async fn typed_call() {
+    let (api, mock) = mock_api("typed_call").await;
+    // Get past the rejected first token.
+    api.current_user().await.unwrap();
+
+    let playback = api.player().await.unwrap().expect("there should be playback");
+    let item = playback.song.item.as_ref().unwrap();
+    assert!(matches!(item, Item::Track(_)));
+    assert_eq!(item.name(), "Money");
+    assert_eq!(playback.device.unwrap().volume_percent, Some(64));
+
+    let requests = &mock.lock().unwrap().requests;
+    assert_eq!(requests.last().unwrap(), "GET /v1/me/player?additional_types=track,episode token-2");
+}
+
+#[actix_web::test]
+async fn refreshes_rejected_token_and_retries() {
+    let (api, mock) = mock_api("refresh").await;
+
+    let user = api.current_user().await.unwrap();
+    assert_eq!(user.id, "driver");
+
+    let mock = mock.lock().unwrap();
+    assert_eq!(mock.tokens_issued, 2);
+    let requests: Vec<&str> = mock.requests.iter()
+        .filter(|request| request.starts_with("GET"))
+        .map(String::as_str)
+        .collect();
+    assert_eq!(requests, vec!["GET /v1/me token-1", "GET /v1/me token-2"]);
+}
+
+#[actix_web::test]
+async fn spotify_error_body() {
+    let (api, _mock) = mock_api("error_body").await;
+    api.current_user().await.unwrap();
+
+    let error = api.next().await.unwrap_err();
+    match &error {
+        Error::Spotify { status, message, reason } => {
+            assert_eq!(*status, StatusCode::NOT_FOUND);
+            assert_eq!(message, "Player command failed: No active device found");
+            assert_eq!(reason.as_deref(), Some("NO_ACTIVE_DEVICE"));
+        }
+        other => panic!("expected a Spotify error, got {:?}", other),
+    }
+    assert_eq!(error.code(), "no_active_device");
+}
+
+#[actix_web::test]
+async fn accounts_outage_keeps_the_login() {
+    let (api, mock) = mock_api("outage").await;
+    mock.lock().unwrap().token_failure = Some(503);
+
+    let error = api.current_user().await.unwrap_err();
+    assert!(matches!(error, Error::Spotify { status: StatusCode::SERVICE_UNAVAILABLE, .. }), "{:?}", error);
+    assert!(api.is_unreachable(&error));
+    assert_eq!(api.tokens().credentials().get().refresh_token.as_deref(), Some("refresh-token"));
+
+    mock.lock().unwrap().token_failure = None;
+    api.tokens().get_access_token().await.unwrap();
+}
+
+#[actix_web::test]
+async fn revoked_refresh_token_logs_out() {
+    let (api, mock) = mock_api("revoked").await;
+    mock.lock().unwrap().token_failure = Some(400);
+
+    let error = api.current_user().await.unwrap_err();
+    assert!(matches!(error, Error::NotLoggedIn), "{:?}", error);
+    assert!(!api.is_unreachable(&error));
+    assert_eq!(api.tokens().credentials().get().refresh_token, None);
+}
+
+#[test]
+fn backoff_after_server_errors_is_not_a_rate_limit() {
+    let rate_limited = Error::Throttled { reason: ThrottleReason::RateLimited, retry_after: Duration::from_millis(1500) };
+    assert_eq!(rate_limited.code(), "rate_limited");
+    assert_eq!(rate_limited.status_code(), StatusCode::TOO_MANY_REQUESTS);
+
+    let unavailable = Error::Throttled { reason: ThrottleReason::ServerError, retry_after: Duration::from_millis(1500) };
+    assert_eq!(unavailable.code(), "spotify_unavailable");
+    assert_eq!(unavailable.status_code(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(unavailable.body().retry_after_ms, Some(1500));
+}
@@ -0,0 +1,129 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+
+use client::credentials::{CredentialStore, DEFAULT_PROFILE};
+
+/// The key source is read from the environment, so tests that set it take turns.
+static ENV: Mutex<()> = Mutex::new(());
+
+/// A fresh directory for the test's store and legacy files, with the passphrase set to
+/// `passphrase` (empty means the machine key, kept under the same directory).
+fn setup(name: &str, passphrase: &str) -> (PathBuf, MutexGuard<'static, ()>) {
+    let guard = ENV.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = std::env::temp_dir().join(format!("beamng-spotify-credentials-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+
+    std::env::set_var("XDG_DATA_HOME", dir.join("data"));
+    set_passphrase(passphrase);
+    (dir, guard)
+}
+
+/// Empty rather than removed, so a `.env` in the working directory can't fill it back in.
+fn set_passphrase(passphrase: &str) {
+    std::env::set_var("SPOTIFY_CREDENTIALS_PASSPHRASE", passphrase);
+}
+
+fn key_source(dir: &Path) -> String {
+    let file: serde_json::Value = serde_json::from_str(&fs::read_to_string(dir.join("credentials.enc")).unwrap()).unwrap();
+    file["key_source"].as_str().unwrap().to_string()
+}
+
+#[test]
+fn round_trip() {
+    let (dir, _env) = setup("round_trip", "test passphrase");
+    let path = dir.join("credentials.enc");
+
+    let store = CredentialStore::open_at(&path).unwrap();
+    store.update(|c| c.refresh_token = Some("default-token".to_string())).unwrap();
+    store.switch_profile("work").unwrap();
+    store.update(|c| c.refresh_token = Some("work-token".to_string())).unwrap();
+    drop(store);
+
+    let contents = fs::read_to_string(&path).unwrap();
+    assert!(!contents.contains("default-token") && !contents.contains("work-token"), "{}", contents);
+
+    let store = CredentialStore::open_at(&path).unwrap();
+    assert_eq!(store.current_profile(), "work");
+    assert_eq!(store.profiles(), vec![DEFAULT_PROFILE.to_string(), "work".to_string()]);
+    assert_eq!(store.get().refresh_token.as_deref(), Some("work-token"));
+    assert_eq!(store.get_profile(DEFAULT_PROFILE).unwrap().refresh_token.as_deref(), Some("default-token"));
+}
+
+#[test]
+fn client_secret_is_shared_by_every_profile() {
+    let (dir, _env) = setup("client_secret", "test passphrase");
+    let path = dir.join("credentials.enc");
+
+    let store = CredentialStore::open_at(&path).unwrap();
+    store.set_client_secret(Some("secret".to_string())).unwrap();
+    store.switch_profile("work").unwrap();
+    assert_eq!(store.client_secret().as_deref(), Some("secret"));
+    drop(store);
+
+    let store = CredentialStore::open_at(&path).unwrap();
+    store.switch_profile("new").unwrap();
+    assert_eq!(store.client_secret().as_deref(), Some("secret"));
+}
+
+#[test]
+fn wrong_passphrase() {
+    let (dir, _env) = setup("wrong_passphrase", "right passphrase");
+    let path = dir.join("credentials.enc");
+    CredentialStore::open_at(&path).unwrap()
+        .update(|c| c.refresh_token = Some("token".to_string()))
+        .unwrap();
+
+    set_passphrase("wrong passphrase");
+    let error = CredentialStore::open_at(&path).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn setting_a_passphrase_re_encrypts_with_it() {
+    let (dir, _env) = setup("set_passphrase", "");
+    let path = dir.join("credentials.enc");
+    CredentialStore::open_at(&path).unwrap()
+        .update(|c| c.refresh_token = Some("token".to_string()))
+        .unwrap();
+    assert_eq!(key_source(&dir), "machine");
+
+    set_passphrase("test passphrase");
+    let store = CredentialStore::open_at(&path).unwrap();
+    assert_eq!(store.get().refresh_token.as_deref(), Some("token"));
+    store.update(|c| c.granted_scopes = Some(Vec::new())).unwrap();
+    assert_eq!(key_source(&dir), "passphrase");
+}
+
+#[test]
+fn passphrase_protected_file_needs_the_passphrase() {
+    let (dir, _env) = setup("unset_passphrase", "test passphrase");
+    let path = dir.join("credentials.enc");
+    CredentialStore::open_at(&path).unwrap()
+        .update(|c| c.refresh_token = Some("token".to_string()))
+        .unwrap();
+
+    set_passphrase("");
+    let error = CredentialStore::open_at(&path).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn imports_legacy_files_next_to_the_store() {
+    let (dir, _env) = setup("migration", "test passphrase");
+    fs::write(dir.join("refresh_token.txt"), "legacy-token\n").unwrap();
+    fs::write(dir.join(".env"), "SPOTIFY_CLIENT_ID=client-id\nSPOTIFY_CLIENT_SECRET=legacy-secret\nSPOTIFY_PORT=8888").unwrap();
+
+    let store = CredentialStore::open_at(dir.join("credentials.enc")).unwrap();
+    let credentials = store.get();
+    assert_eq!(credentials.refresh_token.as_deref(), Some("legacy-token"));
+    assert_eq!(store.client_secret().as_deref(), Some("legacy-secret"));
+
+    assert!(!dir.join("refresh_token.txt").exists());
+    assert_eq!(
+        fs::read_to_string(dir.join(".env")).unwrap(),
+        "SPOTIFY_CLIENT_ID=client-id\nSPOTIFY_CLIENT_SECRET=\nSPOTIFY_PORT=8888",
+    );
+}
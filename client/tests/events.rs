@@ -0,0 +1,109 @@
+use client::events::{Event, diff};
+use client::playback::Playback;
+use client::state::PlayerState;
+
+/// Duration of the track in `player_track.json`.
+const DURATION_MS: u64 = 382296;
+
+/// The fixture track playing at `progress_ms`, polled at `fetched_at`.
+fn state(fetched_at: u64, progress_ms: u64) -> PlayerState {
+    let mut playback: Playback = serde_json::from_str(include_str!("fixtures/player_track.json")).unwrap();
+    playback.song.progress_ms = Some(progress_ms);
+
+    PlayerState {
+        playback: Some(playback),
+        fetched_at,
+        version: 0,
+    }
+}
+
+fn playback(state: &mut PlayerState) -> &mut Playback {
+    state.playback.as_mut().unwrap()
+}
+
+fn seeked(events: &[Event]) -> Option<u64> {
+    events.iter().find_map(|event| match event {
+        Event::Seeked { progress_ms } => Some(*progress_ms),
+        _ => None,
+    })
+}
+
+#[test]
+fn replay_when_repeat_one_wraps() {
+    let mut old = state(0, DURATION_MS - 1000);
+    playback(&mut old).repeat_state = Some("track".to_string());
+    let mut new = state(2000, 1000);
+    playback(&mut new).repeat_state = Some("track".to_string());
+
+    let events = diff(&old, &new);
+    assert!(events.iter().any(|event| matches!(event, Event::TrackChanged { replay: true, .. })), "{:?}", events);
+    assert_eq!(seeked(&events), None);
+}
+
+#[test]
+fn same_track_far_from_the_start_is_a_seek() {
+    let events = diff(&state(0, 200_000), &state(1000, 30_000));
+
+    assert!(!events.iter().any(|event| matches!(event, Event::TrackChanged { .. })), "{:?}", events);
+    assert_eq!(seeked(&events), Some(30_000));
+}
+
+#[test]
+fn progress_within_tolerance_is_not_a_seek() {
+    // A second later and a little off from where playback alone would be.
+    let events = diff(&state(0, 10_000), &state(1000, 12_500));
+
+    assert_eq!(seeked(&events), None);
+    assert!(!events.iter().any(|event| matches!(event, Event::TrackChanged { .. })), "{:?}", events);
+    assert!(events.iter().any(|event| matches!(event, Event::Progress { progress_ms: 12_500, server_time: 1000, .. })), "{:?}", events);
+}
+
+#[test]
+fn jump_beyond_tolerance_is_a_seek() {
+    let events = diff(&state(0, 10_000), &state(1000, 60_000));
+    assert_eq!(seeked(&events), Some(60_000));
+}
+
+#[test]
+fn volume_change_on_the_same_device() {
+    let old = state(0, 10_000);
+    let mut new = state(1000, 11_000);
+    playback(&mut new).device.as_mut().unwrap().volume_percent = Some(30);
+
+    let events = diff(&old, &new);
+    assert!(events.iter().any(|event| matches!(event, Event::VolumeChanged { volume_percent: Some(30) })), "{:?}", events);
+    assert!(!events.iter().any(|event| matches!(event, Event::DeviceChanged { .. })), "{:?}", events);
+}
+
+#[test]
+fn device_change_is_not_a_volume_change() {
+    let old = state(0, 10_000);
+    let mut new = state(1000, 11_000);
+    let device = playback(&mut new).device.as_mut().unwrap();
+    device.id = Some("phone".to_string());
+    device.volume_percent = Some(100);
+
+    let events = diff(&old, &new);
+    let device_id = events.iter().find_map(|event| match event {
+        Event::DeviceChanged { device } => Some(device.as_ref().and_then(|device| device.id.clone())),
+        _ => None,
+    });
+    assert_eq!(device_id, Some(Some("phone".to_string())));
+    assert!(!events.iter().any(|event| matches!(event, Event::VolumeChanged { .. })), "{:?}", events);
+}
+
+#[test]
+fn context_shuffle_and_repeat_changes() {
+    let old = state(0, 10_000);
+    assert!(diff(&old, &state(1000, 11_000)).iter().all(|event| matches!(event, Event::Progress { .. })));
+
+    let mut new = state(1000, 11_000);
+    playback(&mut new).song.context = None;
+    playback(&mut new).shuffle_state = Some(true);
+    playback(&mut new).repeat_state = Some("off".to_string());
+
+    let events = diff(&old, &new);
+    assert!(events.iter().any(|event| matches!(event, Event::ContextChanged { context: None })), "{:?}", events);
+    assert!(events.iter().any(|event| matches!(event, Event::ShuffleChanged { shuffle_state: true })), "{:?}", events);
+    assert!(events.iter().any(|event| matches!(event, Event::RepeatChanged { repeat_state } if repeat_state == "off")), "{:?}", events);
+}
@@ -0,0 +1,139 @@
+use client::api::Page;
+use client::compact::compact;
+use client::library::LibraryList;
+use client::playback::Playback;
+use client::playlist::PlaylistTrack;
+use client::song::Item;
+
+fn playback(fixture: &str) -> Playback {
+    serde_json::from_str(fixture).expect("fixture should parse")
+}
+
+#[test]
+fn track() {
+    let playback = playback(include_str!("fixtures/player_track.json"));
+    let item = playback.song.item.as_ref().unwrap();
+
+    let track = match item {
+        Item::Track(track) => track,
+        other => panic!("expected a track, got {:?}", other),
+    };
+    assert_eq!(track.name, "Money");
+    assert_eq!(track.album.images.len(), 3);
+    assert_eq!(item.artists(), vec!["Pink Floyd"]);
+    assert_eq!(item.duration_ms(), Some(382296));
+    assert_eq!(playback.song.progress_ms, Some(84512));
+    assert_eq!(playback.song.context.as_ref().unwrap().type_, "playlist");
+    assert_eq!(playback.repeat_state.as_deref(), Some("context"));
+
+    let device = playback.device.unwrap();
+    assert_eq!(device.id.as_deref(), Some("5fbb3ba6aa454b5534c4ba43a8c7e8e45a63ad0e"));
+    assert_eq!(device.volume_percent, Some(64));
+}
+
+#[test]
+fn episode() {
+    let playback = playback(include_str!("fixtures/player_episode.json"));
+    let item = playback.song.item.as_ref().unwrap();
+
+    let episode = match item {
+        Item::Episode(episode) => episode,
+        other => panic!("expected an episode, got {:?}", other),
+    };
+    assert_eq!(episode.name, "Group B: The Fastest Era");
+    assert_eq!(episode.show.as_ref().unwrap().publisher.as_deref(), Some("Pit Lane Media"));
+    assert_eq!(item.artists(), vec!["Pit Lane Stories"]);
+    assert_eq!(item.uri(), "spotify:episode:512ojhOuo1ktJprKbVcKyQ");
+    assert_eq!(item.duration_ms(), Some(3529586));
+    assert_eq!(episode.resume_point.as_ref().unwrap().resume_position_ms, 1203311);
+    assert_eq!(playback.song.currently_playing_type.as_deref(), Some("episode"));
+}
+
+#[test]
+fn local_file() {
+    let playback = playback(include_str!("fixtures/player_local_file.json"));
+    let item = playback.song.item.as_ref().unwrap();
+
+    let track = match item {
+        Item::Track(track) => track,
+        other => panic!("expected a track, got {:?}", other),
+    };
+    assert!(track.is_local);
+    assert_eq!(track.id, None);
+    assert_eq!(track.album.release_date, None);
+    assert!(track.album.images.is_empty());
+    assert_eq!(item.artists(), vec!["The Mechanics"]);
+    assert_eq!(item.uri(), "spotify:local:The+Mechanics:Garage+Tapes:Redline:214");
+}
+
+#[test]
+fn ad_on_restricted_device() {
+    let playback = playback(include_str!("fixtures/player_ad.json"));
+
+    assert!(playback.song.item.is_none());
+    assert_eq!(playback.song.currently_playing_type.as_deref(), Some("ad"));
+    assert_eq!(playback.song.is_playing, Some(true));
+
+    let device = playback.device.unwrap();
+    assert_eq!(device.id, None);
+    assert_eq!(device.volume_percent, None);
+    assert!(device.is_restricted);
+}
+
+#[test]
+fn unknown_item_is_kept() {
+    let playback = playback(include_str!("fixtures/player_unknown.json"));
+    let item = playback.song.item.as_ref().unwrap();
+
+    assert!(matches!(item, Item::Unknown(_)), "expected an unknown item, got {:?}", item);
+    assert_eq!(item.name(), "Chapter 3");
+    assert_eq!(item.duration_ms(), Some(1800000));
+
+    // Passed on as Spotify sent it.
+    let json = serde_json::to_value(item).unwrap();
+    assert_eq!(json["type"], "chapter");
+}
+
+#[test]
+fn items_serialize_as_sent() {
+    let playback = playback(include_str!("fixtures/player_episode.json"));
+    let json = serde_json::to_value(&playback).unwrap();
+
+    assert_eq!(json["item"]["type"], "episode");
+    assert_eq!(json["item"]["show"]["name"], "Pit Lane Stories");
+    assert_eq!(json["device"]["volume_percent"], 40);
+
+    let reparsed: Playback = serde_json::from_value(json).unwrap();
+    assert!(matches!(reparsed.song.item, Some(Item::Episode(_))));
+}
+
+#[test]
+fn playlist_tracks() {
+    let page: Page<PlaylistTrack> = serde_json::from_str(include_str!("fixtures/playlist_tracks.json")).unwrap();
+    assert_eq!(page.total, 4);
+
+    let kinds: Vec<&str> = page.items.iter()
+        .map(|item| match &item.track {
+            Some(Item::Track(track)) if track.is_local => "local",
+            Some(Item::Track(_)) => "track",
+            Some(Item::Episode(_)) => "episode",
+            Some(Item::Unknown(_)) => "unknown",
+            None => "removed",
+        })
+        .collect();
+    assert_eq!(kinds, vec!["track", "episode", "removed", "local"]);
+}
+
+#[test]
+fn compact_playlist_tracks() {
+    let page: Page<serde_json::Value> = serde_json::from_str(include_str!("fixtures/playlist_tracks.json")).unwrap();
+    let list = LibraryList::PlaylistTracks("3cEYpjA9oz9GiPac4AsH4n".to_string());
+
+    let items: Vec<_> = page.items.iter().filter_map(|item| compact(&list, item)).collect();
+    let names: Vec<&str> = items.iter().map(|item| item.name.as_str()).collect();
+    assert_eq!(names, vec!["Cut To The Feeling", "Group B: The Fastest Era", "Redline"]);
+
+    assert_eq!(items[0].artists, vec!["Carly Rae Jepsen"]);
+    assert_eq!(items[0].image_url.as_deref(), Some("https://i.scdn.co/image/ab67616d000048517359994525d219f64872d3b1"));
+    assert_eq!(items[2].id, "");
+}
@@ -5,6 +5,10 @@ http.TIMEOUT = 0.1
 
 local imgui = ui_imgui
 
+-- Override with {"base_url": "http://localhost:9999"} in settings/spotify.json when the client runs elsewhere.
+local settings = jsonReadFile("settings/spotify.json") or {}
+local base_url = settings.base_url or "http://localhost:8888"
+
 local connected = true
 local old_connected = true
 local current_song = {}
@@ -13,13 +17,49 @@ local playlists = {}
 local tracks = {}
 local top_songs = {}
 local active_playlist = nil
+local auth_status = nil
+local last_error = nil
+
+local error_messages = {
+    not_logged_in = "Not logged in to Spotify",
+    no_active_device = "No active device, start playing something in Spotify first",
+    premium_required = "Spotify Premium is required to control playback",
+}
 
 local volume = imgui.IntPtr(0)
 local attempts = 0
 local max_attempts = 4
 
+-- Remembers the error envelope ({"error": "<code>", "message": ...}) from a response, if any.
+local function handle_response(respbody)
+    local body = table.concat(respbody)
+    if body == "" then
+        last_error = nil
+        return
+    end
+
+    local data = jsonDecode(body)
+    if type(data) == "table" and type(data.error) == "string" then
+        last_error = data
+        log("W", "spotify", data.message or data.error)
+    else
+        last_error = nil
+    end
+end
+
+local function post(url)
+    local respbody = {}
+    http.request {
+        url = url,
+        method = "POST",
+        sink = ltn12.sink.table(respbody),
+    }
+
+    handle_response(respbody)
+end
+
 local function get_song()
-    local body = http.request("http://localhost:8888/api/v1/current_song")
+    local body = http.request(base_url .. "/api/v1/current_song")
 
     if not body then
         attempts = attempts + 1
@@ -34,7 +74,7 @@ local function get_song()
 end
 
 local function get_active_device()
-    local body = http.request("http://localhost:8888/api/v1/active_device")
+    local body = http.request(base_url .. "/api/v1/active_device")
 
     if not body then
         attempts = attempts + 1
@@ -48,22 +88,54 @@ local function get_active_device()
     return jsonDecode(body)
 end
 
-local function next_song()
-    local url = "http://localhost:8888/api/v1/next_song"
+-- Song and device in one request. LuaSocket blocks the game, so this never waits for changes
+-- (`since`/`timeout`), that's for clients that can.
+local function get_state()
+    local body = http.request(base_url .. "/api/v1/state")
+
+    if not body then
+        attempts = attempts + 1
+        return nil
+    else
+        connected = true
+        old_connected = true
+        attempts = 0
+    end
+
+    return jsonDecode(body)
+end
+
+local function get_auth_status()
+    local body = http.request(base_url .. "/api/v1/auth/status")
+    if not body then
+        return auth_status
+    end
+
+    return jsonDecode(body)
+end
+
+local function start_login()
     http.request {
-        url = url,
+        url = base_url .. "/api/v1/auth/start",
         method = "POST",
     }
 end
 
-local function previous_song()
-    local url = "http://localhost:8888/api/v1/previous_song"
+local function logout()
     http.request {
-        url = url,
+        url = base_url .. "/api/v1/auth/logout",
         method = "POST",
     }
 end
 
+local function next_song()
+    post(base_url .. "/api/v1/next_song")
+end
+
+local function previous_song()
+    post(base_url .. "/api/v1/previous_song")
+end
+
 local function play_song(id, uris, pos)
     local body = ""
     if id then
@@ -79,7 +151,7 @@ local function play_song(id, uris, pos)
         })
     end
 
-    local url = "http://localhost:8888/api/v1/play_song"
+    local url = base_url .. "/api/v1/play_song"
     local respbody = {}
     http.request {
         url = url,
@@ -95,11 +167,13 @@ local function play_song(id, uris, pos)
         },
         sink = ltn12.sink.table(respbody),
     }
+
+    handle_response(respbody)
 end
 
 local function get_playlists()
     http.TIMEOUT = 5 -- I know, it's a lot.
-    local body = http.request("http://localhost:8888/api/v1/playlists")
+    local body = http.request(base_url .. "/api/v1/playlists?all=true&compact=true")
     http.TIMEOUT = 0.1
 
     if not body then
@@ -116,7 +190,7 @@ end
 
 local function get_tracks(playlist_id)
     http.TIMEOUT = 5 -- I know, it's a lot.
-    local body = http.request("http://localhost:8888/api/v1/playlists/" .. playlist_id .. "/tracks")
+    local body = http.request(base_url .. "/api/v1/playlists/" .. playlist_id .. "/tracks?all=true&compact=true")
     http.TIMEOUT = 0.1
 
     if not body then
@@ -133,7 +207,7 @@ end
 
 local function get_top_songs()
     http.TIMEOUT = 5 -- I know, it's a lot.
-    local body = http.request("http://localhost:8888/api/v1/top_tracks")
+    local body = http.request(base_url .. "/api/v1/top_tracks?compact=true")
     http.TIMEOUT = 0.1
 
     if not body then
@@ -149,19 +223,11 @@ local function get_top_songs()
 end
 
 local function pause_song()
-    local url = "http://localhost:8888/api/v1/pause_song"
-    http.request {
-        url = url,
-        method = "POST",
-    }
+    post(base_url .. "/api/v1/pause_song")
 end
 
 local function seek(time_ms)
-    local url = "http://localhost:8888/api/v1/seek/" .. time_ms
-    http.request {
-        url = url,
-        method = "POST",
-    }
+    post(base_url .. "/api/v1/seek/" .. time_ms)
 end
 
 local function set_volume(volume)
@@ -170,15 +236,13 @@ local function set_volume(volume)
         return
     end
 
-    local url = "http://localhost:8888/api/v1/volume/" .. tostring(volume)
-    http.request {
-        url = url,
-        method = "POST",
-    }
+    post(base_url .. "/api/v1/volume/" .. tostring(volume))
 end
 
 local last_update = 0
+local auth_update = 0
 local volume_update = 0
+local seek_update = 0
 local volume_changed = false
 local pushed = false
 
@@ -186,9 +250,11 @@ local function draw_playlist()
     if not active_playlist then return end
 
     local playlist_tracks = tracks[active_playlist]
+    if not playlist_tracks or not playlist_tracks.items then return end
+
     for i, track in pairs(playlist_tracks.items) do
-        local song_name = track.track.name
-        local song_id = track.track.id
+        local song_name = track.name
+        local song_id = track.id
 
         if current_song and current_song.item and song_id == current_song.item.id then
             imgui.PushStyleColor2(imgui.Col_Button, imgui.ImVec4(0.5, 0.5, 0.5, 1))
@@ -198,10 +264,10 @@ local function draw_playlist()
         if imgui.Button(song_name, imgui.ImVec2(imgui.GetWindowWidth(), 24)) then
             local all_songs_in_playlist = {}
             for _, song in pairs(playlist_tracks.items) do
-                table.insert(all_songs_in_playlist, "spotify:track:" .. song.track.id)
+                table.insert(all_songs_in_playlist, song.uri)
             end
 
-            play_song(track.track.id, all_songs_in_playlist, i-1)
+            play_song(track.id, all_songs_in_playlist, i-1)
         end
 
         if pushed then
@@ -224,7 +290,7 @@ local function draw_top()
         if imgui.Button(name, imgui.ImVec2(imgui.GetWindowWidth(), 24)) then
             local all_top_songs = {}
             for _, song in pairs(top_songs) do
-                table.insert(all_top_songs, "spotify:track:" .. song.id)
+                table.insert(all_top_songs, song.uri)
             end
 
             play_song(id, all_top_songs, i-1)
@@ -255,17 +321,19 @@ local function onUpdate()
     if not connected then return end
 
     local now = os.clock()
-    if os.clock() - last_update > 0.1 then
+    -- The client extrapolates progress itself, so this only needs to be often enough for a smooth bar.
+    if os.clock() - last_update > 0.25 then
         last_update = now
-        current_song = get_song()
-        active_device = get_active_device()
+        local state = get_state()
+        current_song = state and state.song
+        active_device = state and state.device
     end
 
     if volume_changed then
         if os.clock() - volume_update > 1.6 then
             volume_update = now
             if active_device and active_device.device then
-                volume = imgui.IntPtr(active_device.device.volume_percent)
+                volume = imgui.IntPtr(active_device.device.volume_percent or 0)
             else
                 log("W", "get_active_device", "failed to get active device")
             end
@@ -276,7 +344,7 @@ local function onUpdate()
         if os.clock() - volume_update > 0.1 then
             volume_update = now
             if active_device and active_device.device then
-                volume = imgui.IntPtr(active_device.device.volume_percent)
+                volume = imgui.IntPtr(active_device.device.volume_percent or 0)
             else
                 log("W", "get_active_device", "failed to get active device")
             end
@@ -284,8 +352,24 @@ local function onUpdate()
     end
 
     if imgui.Begin("Spotify Controller") then
+        if last_error then
+            imgui.Text(error_messages[last_error.error] or last_error.message or last_error.error)
+        end
+
         local song = current_song
         if not song or song and not song.item then
+            if os.clock() - auth_update > 2 then
+                auth_update = now
+                auth_status = get_auth_status()
+            end
+
+            if auth_status and not auth_status.logged_in then
+                imgui.Text("Not logged in to Spotify")
+                if imgui.Button("Log in to Spotify") then
+                    start_login()
+                end
+            end
+
             imgui.End()
             return
         end
@@ -311,15 +395,19 @@ local function onUpdate()
             local time_seconds = math.floor((song.progress_ms % 60000) / 1000)
             local time_str = string.format("%02d:%02d", time_minutes, time_seconds)
             local time_ms = song.progress_ms
-            local duration_ms = song.item.duration_ms
-            local progress = time_ms / duration_ms
+            local duration_ms = song.item.duration_ms or 0
+            local progress = duration_ms > 0 and time_ms / duration_ms or 0
             imgui.ProgressBar(progress, imgui.ImVec2(0.0, 0.0), time_str)
             if imgui.IsItemHovered() and imgui.IsMouseDown(0) then
                 local mouse_x = imgui.GetMousePos().x
                 local progress_x = imgui.GetItemRectMin().x
                 local progress_width = imgui.GetItemRectSize().x
                 local time_ms = math.floor((mouse_x - progress_x) / progress_width * duration_ms)
-                seek(time_ms)
+                -- Dragging would otherwise send a seek every frame and get us rate limited.
+                if now - seek_update > 0.25 then
+                    seek_update = now
+                    seek(time_ms)
+                end
             end
 
             imgui.SameLine()
@@ -411,13 +499,25 @@ local function is_connected()
     return connected
 end
 
+local function set_base_url(url)
+    base_url = url
+    reconnect()
+end
+
 local function onExtensionLoaded()
+    -- Errors (e.g. not logged in yet) come back as {"error": ...} without items.
     playlists = get_playlists()
+    if not playlists or not playlists.items then
+        playlists = nil
+        return
+    end
+
     for _, playlist in pairs(playlists.items) do
         tracks[playlist.id] = get_tracks(playlist.id)
     end
 
-    top_songs = get_top_songs().items
+    local top = get_top_songs()
+    top_songs = top and top.items or {}
 end
 
 M.onExtensionLoaded = onExtensionLoaded
@@ -425,10 +525,16 @@ M.onUpdate = onUpdate
 
 M.reconnect = reconnect
 M.is_connected = is_connected
+M.set_base_url = set_base_url
 
 M.get_playlists = get_playlists
 M.get_tracks = get_tracks
 
+M.get_auth_status = get_auth_status
+M.start_login = start_login
+M.logout = logout
+
+M.get_state = get_state
 M.get_song = get_song
 M.get_active_device = get_active_device
 